@@ -0,0 +1,199 @@
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::types::{PyDict, PyModule, PyString, PyTuple, PyType};
+use pyo3::{PyAny, PyResult, Python};
+
+use crate::shareable::{ShareError, SharedValue};
+use crate::transport::Transport;
+
+#[derive(Debug, Clone)]
+/// A reference to a module level function which can be looked up within any interpreter.
+///
+/// Functions themselves cannot be shared, so like `pickle`, they are passed by name
+/// and imported again by the interpreter calling them.
+pub(crate) struct FunctionRef {
+    module: String,
+    qualname: String,
+}
+
+impl FunctionRef {
+    /// Creates a reference from either a function or its qualified name.
+    ///
+    /// Names may be given as either `"module:qualname"` or `"module.qualname"`,
+    /// in the latter case the last part of the name is assumed to be the function.
+    pub(crate) fn from_py(obj: &PyAny) -> PyResult<Self> {
+        if let Ok(name) = obj.downcast::<PyString>() {
+            return Self::parse(name.to_str()?);
+        }
+
+        let module = obj.getattr("__module__")?.extract::<Option<String>>()?;
+        let qualname = obj.getattr("__qualname__")?.extract::<String>()?;
+
+        // Builtin functions are bound to their module and classmethods to their class,
+        // which are looked up again anyway, but looking up any other bound method by name
+        // would lose what it's bound to.
+        if let Ok(bound_to) = obj.getattr("__self__") {
+            if !bound_to.is_instance_of::<PyModule>() && !is_bound_to_owner(bound_to, &qualname) {
+                return Err(PyTypeError::new_err(format!(
+                    "`{qualname}` is a bound method, which cannot be called by other \
+                    interpreters since the object it is bound to cannot be shared.",
+                )));
+            }
+        }
+
+        let module = match module {
+            Some(module) if module != "__main__" => module,
+            _ => {
+                return Err(PyTypeError::new_err(format!(
+                    "`{qualname}` is not defined within an importable module, \
+                    functions from `__main__` cannot be called by other interpreters.",
+                )))
+            }
+        };
+
+        if qualname.contains('<') {
+            return Err(PyTypeError::new_err(format!(
+                "`{module}.{qualname}` cannot be called by other interpreters, \
+                only functions and classes defined at the module level can be.",
+            )));
+        }
+
+        Ok(Self { module, qualname })
+    }
+
+    fn parse(name: &str) -> PyResult<Self> {
+        let split = name.split_once(':').or_else(|| name.rsplit_once('.'));
+
+        match split {
+            Some((module, qualname)) if !module.is_empty() && !qualname.is_empty() => Ok(Self {
+                module: module.to_string(),
+                qualname: qualname.to_string(),
+            }),
+            _ => Err(PyValueError::new_err(format!(
+                "`{name}` is not a qualified function name, expected `module.function`.",
+            ))),
+        }
+    }
+
+    /// Imports the module and looks up the function within the currently active interpreter.
+    pub(crate) fn resolve<'py>(&self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        let mut obj: &PyAny = py.import(self.module.as_str())?;
+        for attr in self.qualname.split('.') {
+            obj = obj.getattr(attr)?;
+        }
+        Ok(obj)
+    }
+}
+
+/// Returns `true` if `bound_to` is the class which defines the method named `qualname`,
+/// which looking the method up by name would bind it to again.
+///
+/// A classmethod inherited from another class is named after that class instead.
+fn is_bound_to_owner(bound_to: &PyAny, qualname: &str) -> bool {
+    let Ok(class) = bound_to.downcast::<PyType>() else {
+        return false;
+    };
+    let Ok(class_name) = class
+        .getattr("__qualname__")
+        .and_then(|name| name.extract::<&str>())
+    else {
+        return false;
+    };
+
+    qualname
+        .strip_prefix(class_name)
+        .and_then(|name| name.strip_prefix('.'))
+        .is_some_and(|name| !name.contains('.'))
+}
+
+#[derive(Debug, Clone, Default)]
+/// A copy of the positional and keyword arguments for a function call.
+pub(crate) struct CallArgs {
+    args: Vec<SharedValue>,
+    kwargs: Vec<(String, SharedValue)>,
+    transport: Transport,
+}
+
+impl CallArgs {
+    pub(crate) fn extract(
+        args: &PyTuple,
+        kwargs: Option<&PyDict>,
+        transport: Transport,
+    ) -> PyResult<Self> {
+        let args = args
+            .iter()
+            .map(|arg| transport.dump(arg))
+            .collect::<PyResult<_>>()?;
+
+        let kwargs = kwargs
+            .map(|kwargs| {
+                kwargs
+                    .iter()
+                    .map(|(key, value)| Ok((key.extract()?, transport.dump(value)?)))
+                    .collect::<PyResult<_>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        Ok(Self {
+            args,
+            kwargs,
+            transport,
+        })
+    }
+
+    /// Calls the function within the currently active interpreter, copying
+    /// the result back out.
+    ///
+    /// Like `Transport::dump_remote`, a result which isn't shareable is returned
+    /// as a `ShareError` rather than raised.
+    pub(crate) fn call(self, func: &PyAny) -> PyResult<Result<SharedValue, ShareError>> {
+        let py = func.py();
+        let transport = self.transport;
+
+        let args = self
+            .args
+            .into_iter()
+            .map(|arg| transport.load(py, arg))
+            .collect::<PyResult<Vec<_>>>()?;
+        let kwargs = PyDict::new(py);
+        for (key, value) in self.kwargs {
+            kwargs.set_item(key, transport.load(py, value)?)?;
+        }
+
+        let result = func.call(PyTuple::new(py, args), Some(kwargs))?;
+        transport.dump_remote(result)
+    }
+}
+
+#[cfg(all(test, feature = "python-module"))]
+mod tests {
+    use pyo3::types::PyDict;
+    use pyo3::Python;
+
+    use super::FunctionRef;
+
+    #[test]
+    fn only_accepts_methods_bound_to_their_own_class() {
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            py.run(
+                "import fractions\n\
+                class Sub(fractions.Fraction): pass\n\
+                owned = fractions.Fraction.from_float\n\
+                inherited = Sub.from_float\n\
+                bound = fractions.Fraction(1).limit_denominator",
+                Some(globals),
+                None,
+            )
+            .unwrap();
+
+            let owned = FunctionRef::from_py(globals.get_item("owned").unwrap()).unwrap();
+            assert_eq!(owned.module, "fractions");
+            assert_eq!(owned.qualname, "Fraction.from_float");
+
+            for name in ["inherited", "bound"] {
+                assert!(FunctionRef::from_py(globals.get_item(name).unwrap()).is_err());
+            }
+        });
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use pyo3::exceptions::{PyTimeoutError, PyValueError};
+use pyo3::types::{PyCFunction, PyCapsule, PyDict, PyTuple};
+use pyo3::{pyclass, pyfunction, pymethods, IntoPy, PyAny, PyObject, PyResult, Python};
+
+use crate::interrupt;
+use crate::shareable::SharedValue;
+use crate::transport::Transport;
+
+/// The name given to capsules holding a channel within a sub-interpreter.
+const CAPSULE_NAME: &str = "subinterpreters.Channel";
+
+/// How long a blocking receive waits before checking for signals (e.g. `KeyboardInterrupt`).
+const SIGNAL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Default)]
+/// The queue shared by every handle to the same channel, regardless of interpreter.
+///
+/// This is an opaque handle, found within `SharedValue::Channel`, which can only be
+/// created and used by the Python module's `Channel` class.
+pub struct ChannelState {
+    queue: Mutex<VecDeque<SharedValue>>,
+    ready: Condvar,
+    /// How values are copied into and out of the queue.
+    transport: Transport,
+}
+
+impl ChannelState {
+    /// Copies the object out of the current interpreter and queues it.
+    fn send(&self, obj: &PyAny) -> PyResult<()> {
+        let value = self.transport.dump(obj)?;
+        let mut queue = self.queue.lock().unwrap();
+        queue.push_back(value);
+        self.ready.notify_one();
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for a value to become available.
+    fn recv_timeout(&self, timeout: Duration) -> Option<SharedValue> {
+        let queue = self.queue.lock().unwrap();
+        let (mut queue, _) = self
+            .ready
+            .wait_timeout_while(queue, timeout, |queue| queue.is_empty())
+            .unwrap();
+        queue.pop_front()
+    }
+
+    fn len(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    /// Receives a value, waiting up to `timeout` or forever if `None`.
+    ///
+    /// The GIL of the current interpreter is released while waiting, and signals (along with
+    /// `SubInterpreter.interrupt` and timeouts) are periodically checked for so a blocked
+    /// receive can still be interrupted.
+    fn recv(&self, py: Python, timeout: Option<Duration>) -> PyResult<PyObject> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            let wait = match deadline {
+                Some(deadline) => deadline
+                    .saturating_duration_since(Instant::now())
+                    .min(SIGNAL_CHECK_INTERVAL),
+                None => SIGNAL_CHECK_INTERVAL,
+            };
+
+            if let Some(value) = py.allow_threads(|| self.recv_timeout(wait)) {
+                return self.transport.load(py, value);
+            }
+
+            py.check_signals()?;
+            interrupt::check_pending(py)?;
+
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(PyTimeoutError::new_err("no value was received in time."));
+            }
+        }
+    }
+}
+
+#[pyclass]
+#[derive(Clone)]
+/// A queue for passing shareable values between interpreters.
+///
+/// A channel can be passed into a sub-interpreter like any other shareable value,
+/// and every copy of it refers to the same underlying queue.
+pub struct Channel(pub(crate) Arc<ChannelState>);
+
+#[pyfunction]
+#[pyo3(signature = (transport = "shared", pickle_protocol = None))]
+/// Creates a new channel for passing values between interpreters.
+///
+/// With the `"pickle"` transport, any object which can be pickled can be sent rather
+/// than just shareable values, see `create_interpreter` for the details.
+pub fn create_channel(
+    py: Python,
+    transport: &str,
+    pickle_protocol: Option<i32>,
+) -> PyResult<Channel> {
+    let state = ChannelState {
+        transport: Transport::from_py(py, transport, pickle_protocol)?,
+        ..ChannelState::default()
+    };
+    Ok(Channel(Arc::new(state)))
+}
+
+#[pymethods]
+impl Channel {
+    /// Send a value through the channel.
+    ///
+    /// The value is copied, so it must be shareable (or picklable, with the `"pickle"` transport).
+    fn send(&self, value: &PyAny) -> PyResult<()> {
+        self.0.send(value)
+    }
+
+    /// Receive a value from the channel, blocking until one is available.
+    fn recv(&self, py: Python) -> PyResult<PyObject> {
+        self.0.recv(py, None)
+    }
+
+    /// Receive a value from the channel, waiting at most `timeout` seconds.
+    ///
+    /// Raises a `TimeoutError` if no value was received in time.
+    fn recv_timeout(&self, py: Python, timeout: f64) -> PyResult<PyObject> {
+        self.0.recv(py, Some(to_duration(timeout)?))
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.len()
+    }
+}
+
+pub(crate) fn to_duration(timeout: f64) -> PyResult<Duration> {
+    Duration::try_from_secs_f64(timeout)
+        .map_err(|_| PyValueError::new_err("timeout must be a non-negative number of seconds."))
+}
+
+/// Creates an object which behaves like a `Channel` within the current sub-interpreter.
+///
+/// Sub-interpreters cannot import this module, so they get a `SimpleNamespace` of plain
+/// functions instead, along with a capsule which lets the channel be shared again.
+pub(crate) fn create_proxy(py: Python, state: Arc<ChannelState>) -> PyResult<PyObject> {
+    let send = {
+        let state = state.clone();
+        PyCFunction::new_closure(
+            py,
+            Some("send\0"),
+            None,
+            move |args: &PyTuple, _kwargs: Option<&PyDict>| -> PyResult<()> {
+                state.send(args.get_item(0)?)
+            },
+        )?
+    };
+
+    let recv = {
+        let state = state.clone();
+        PyCFunction::new_closure(
+            py,
+            Some("recv\0"),
+            None,
+            move |args: &PyTuple, _kwargs: Option<&PyDict>| -> PyResult<PyObject> {
+                state.recv(args.py(), None)
+            },
+        )?
+    };
+
+    let recv_timeout = {
+        let state = state.clone();
+        PyCFunction::new_closure(
+            py,
+            Some("recv_timeout\0"),
+            None,
+            move |args: &PyTuple, _kwargs: Option<&PyDict>| -> PyResult<PyObject> {
+                let py = args.py();
+                let timeout = to_duration(args.get_item(0)?.extract()?)?;
+                state.recv(py, Some(timeout))
+            },
+        )?
+    };
+
+    let name = CString::new(CAPSULE_NAME).unwrap();
+    let capsule = PyCapsule::new(py, state, Some(name))?;
+
+    let attrs = PyDict::new(py);
+    attrs.set_item("send", send)?;
+    attrs.set_item("recv", recv)?;
+    attrs.set_item("recv_timeout", recv_timeout)?;
+    attrs.set_item("_channel", capsule)?;
+
+    let namespace = py.import("types")?.getattr("SimpleNamespace")?;
+    Ok(namespace.call((), Some(attrs))?.into_py(py))
+}
+
+/// Gets the channel behind a proxy created by `create_proxy`, if `obj` is one.
+pub(crate) fn extract_proxy(obj: &PyAny) -> Option<Arc<ChannelState>> {
+    if obj.get_type().name().ok()? != "SimpleNamespace" {
+        return None;
+    }
+
+    let capsule = obj.getattr("_channel").ok()?.downcast::<PyCapsule>().ok()?;
+    if capsule.name().ok()??.to_str().ok()? != CAPSULE_NAME {
+        return None;
+    }
+
+    // SAFETY:
+    // Capsules with this name are only ever created by `create_proxy`.
+    let state = unsafe { capsule.reference::<Arc<ChannelState>>() };
+    Some(state.clone())
+}
+
+#[cfg(all(test, feature = "python-module"))]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use pyo3::exceptions::PyKeyboardInterrupt;
+    use pyo3::Python;
+
+    use super::ChannelState;
+    use crate::interrupt::Signal;
+    use crate::{Interpreter, InterpreterConfig};
+
+    #[test]
+    fn blocked_recv_can_be_interrupted() {
+        let interpreter =
+            Python::with_gil(|py| Interpreter::create(py, InterpreterConfig::isolated())).unwrap();
+        let interrupt = interpreter.interrupt().clone();
+        let state = ChannelState::default();
+
+        let interrupted = interpreter.scope(|py| {
+            thread::scope(|scope| {
+                scope.spawn(|| {
+                    while !interrupt.interrupt(Signal::Interrupt) {
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                });
+
+                let err = state.recv(py, None).unwrap_err();
+                err.is_instance_of::<PyKeyboardInterrupt>(py)
+            })
+        });
+        assert!(interrupted);
+    }
+}
@@ -0,0 +1,36 @@
+import ast
+
+# The code objects compiled by this interpreter, from the least to the most recently used.
+cache = {}
+# The same for `CompiledCode` handles, keyed by their ID.
+compiled = {}
+
+
+def compile_source(source, filename, indent, mode="exec"):
+    """
+    Compiles the source as if the `indent` removed from the start of its lines was still
+    there, so the positions shown in tracebacks match the original code.
+    """
+
+    if not indent:
+        return compile(source, filename, mode, dont_inherit=True)
+
+    try:
+        tree = ast.parse(source, filename, mode)
+    except SyntaxError as e:
+        if e.offset:
+            e.offset += len(indent)
+        if e.end_offset:
+            e.end_offset += len(indent)
+        if e.text is not None:
+            e.text = indent + e.text
+        # Hide the frames from `ast.parse`, they aren't part of the caller's code.
+        raise e.with_traceback(None)
+
+    for node in ast.walk(tree):
+        if isinstance(getattr(node, "col_offset", None), int):
+            node.col_offset += len(indent)
+        if isinstance(getattr(node, "end_col_offset", None), int):
+            node.end_col_offset += len(indent)
+
+    return compile(tree, filename, mode, dont_inherit=True)
@@ -0,0 +1,314 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use pyo3::types::{PyDict, PyModule};
+use pyo3::{pyclass, pymethods, PyAny, PyObject, PyResult, Python, ToPyObject};
+
+use crate::subinterpreter::{import_embedded, main_namespace};
+
+/// The maximum number of code objects each interpreter keeps compiled from source code,
+/// and separately the maximum number it keeps for `CompiledCode` handles.
+const CACHE_SIZE: usize = 256;
+
+/// The name the module holding the compile cache is imported as within each interpreter.
+const MODULE_NAME: &str = "_subinterpreters_code";
+
+/// The ID given to the next `CompiledCode`.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone)]
+/// Python source code, along with the indentation removed from it.
+pub(crate) struct SourceCode {
+    code: Arc<str>,
+    filename: Arc<str>,
+    indent: Arc<str>,
+}
+
+impl SourceCode {
+    /// Creates the source, first removing the indentation shared by every line if `dedent` is `true`.
+    pub(crate) fn new(code: &str, filename: &str, dedent: bool) -> Self {
+        let (code, indent) = if dedent {
+            self::dedent(code)
+        } else {
+            (code.to_string(), String::new())
+        };
+
+        Self {
+            code: code.into(),
+            filename: filename.into(),
+            indent: indent.into(),
+        }
+    }
+
+    /// Evaluates the code as an expression within the current interpreter's `__main__`
+    /// namespace, returning the result.
+    ///
+    /// Expressions are cached alongside scripts, so evaluating the same one again
+    /// (e.g. once for every item given to `InterpreterPool.map`) skips compiling it.
+    pub(crate) fn eval<'py>(&self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        let key = (&*self.filename, &*self.indent, &*self.code, "eval").to_object(py);
+        let code = compile_cached(py, "cache", key, self, "eval")?;
+
+        py.import("builtins")?
+            .getattr("eval")?
+            .call1((code, main_namespace(py)?))
+    }
+}
+
+#[pyclass(frozen)]
+#[derive(Debug, Clone)]
+/// Python code compiled ahead of time by `SubInterpreter.compile`.
+///
+/// The code object itself stays within the interpreter which compiled it, so running
+/// the same code again skips compiling it. The handle can still be run by any other
+/// interpreter, which compiles the code itself the first time.
+///
+/// Each interpreter keeps the code for the 256 most recently run handles, separately
+/// from code run as a string, and compiles the code again if it was evicted.
+pub struct CompiledCode {
+    id: u64,
+    source: SourceCode,
+}
+
+impl CompiledCode {
+    pub(crate) fn new(source: SourceCode) -> Self {
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            source,
+        }
+    }
+}
+
+#[pymethods]
+impl CompiledCode {
+    #[getter]
+    /// The source code which was compiled, after removing any indentation.
+    fn source(&self) -> &str {
+        &self.source.code
+    }
+
+    #[getter]
+    /// The filename the code was compiled with, which is shown in tracebacks.
+    fn filename(&self) -> &str {
+        &self.source.filename
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<CompiledCode filename={:?}>", self.source.filename)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Source code to run within an interpreter.
+pub(crate) enum Source {
+    /// Code given as a string, which is cached by its contents.
+    Text(SourceCode),
+    /// Code compiled ahead of time, which is cached by its handle.
+    Compiled(CompiledCode),
+}
+
+impl Source {
+    /// Runs the code within the current interpreter.
+    ///
+    /// Like `exec`, `locals` defaults to `globals`, which defaults to the `__main__` namespace.
+    pub(crate) fn run(
+        &self,
+        py: Python,
+        globals: Option<&PyDict>,
+        locals: Option<&PyDict>,
+    ) -> PyResult<()> {
+        let code = self.compile(py)?;
+        let globals = match globals {
+            Some(globals) => globals,
+            None => main_namespace(py)?,
+        };
+
+        py.import("builtins")?.getattr("exec")?.call1((
+            code,
+            globals,
+            locals.unwrap_or(globals),
+        ))?;
+        Ok(())
+    }
+
+    /// Gets the code object within the current interpreter, only compiling the
+    /// code if it isn't already within the interpreter's cache.
+    pub(crate) fn compile<'py>(&self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        match self {
+            Self::Text(source) => {
+                let key = (&*source.filename, &*source.indent, &*source.code, "exec");
+                compile_cached(py, "cache", key.to_object(py), source, "exec")
+            }
+            // Kept apart from code given as strings, so running lots of different
+            // strings doesn't evict the code which was compiled ahead of time.
+            Self::Compiled(compiled) => compile_cached(
+                py,
+                "compiled",
+                compiled.id.to_object(py),
+                &compiled.source,
+                "exec",
+            ),
+        }
+    }
+}
+
+/// Gets the code object stored under `key` within the given cache of the current
+/// interpreter, compiling it with `mode` if it isn't there.
+fn compile_cached<'py>(
+    py: Python<'py>,
+    cache: &str,
+    key: PyObject,
+    source: &SourceCode,
+    mode: &str,
+) -> PyResult<&'py PyAny> {
+    let module = module(py)?;
+    let cache = module.getattr(cache)?.downcast::<PyDict>()?;
+
+    // Dicts keep their insertion order, so moving an entry to the end on every use
+    // leaves the least recently used entry at the start.
+    if let Some(compiled) = cache.get_item(&key) {
+        cache.del_item(&key)?;
+        cache.set_item(&key, compiled)?;
+        return Ok(compiled);
+    }
+
+    let compiled = module.getattr("compile_source")?.call1((
+        &*source.code,
+        &*source.filename,
+        &*source.indent,
+        mode,
+    ))?;
+
+    if cache.len() >= CACHE_SIZE {
+        let oldest = cache.iter().next().map(|(key, _)| key);
+        if let Some(oldest) = oldest {
+            cache.del_item(oldest)?;
+        }
+    }
+    cache.set_item(key, compiled)?;
+
+    Ok(compiled)
+}
+
+/// Gets the module holding the compile cache for the current interpreter,
+/// importing it the first time.
+fn module(py: Python<'_>) -> PyResult<&PyModule> {
+    import_embedded(
+        py,
+        MODULE_NAME,
+        "subinterpreters/compile.py",
+        include_str!("compile.py"),
+    )
+}
+
+/// Removes the indentation shared by every line of the code, returning the
+/// indentation which was removed.
+///
+/// Unlike `textwrap.dedent`, blank lines are kept so every line stays on the same
+/// line number, and the first line is treated the same as any other.
+fn dedent(code: &str) -> (String, String) {
+    let indent = code
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| &line[..line.len() - line.trim_start_matches([' ', '\t']).len()])
+        .reduce(|common, indent| {
+            let shared = common
+                .bytes()
+                .zip(indent.bytes())
+                .take_while(|(a, b)| a == b)
+                .count();
+            &common[..shared]
+        })
+        .unwrap_or_default();
+
+    if indent.is_empty() {
+        return (code.to_string(), String::new());
+    }
+
+    let mut dedented = String::with_capacity(code.len());
+    for line in code.split_inclusive('\n') {
+        match line.strip_prefix(indent) {
+            Some(line) => dedented.push_str(line),
+            // Only blank lines can have less indentation, which are left empty.
+            None => dedented.push_str(&line[line.trim_end_matches(['\r', '\n']).len()..]),
+        }
+    }
+
+    (dedented, indent.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::dedent;
+
+    #[test]
+    fn removes_shared_indentation() {
+        let (code, indent) = dedent("    a = 1\n    if a:\n        b = 2\n");
+        assert_eq!(code, "a = 1\nif a:\n    b = 2\n");
+        assert_eq!(indent, "    ");
+    }
+
+    #[test]
+    fn keeps_unindented_code() {
+        let (code, indent) = dedent("a = 1\n    b = 2\n");
+        assert_eq!(code, "a = 1\n    b = 2\n");
+        assert_eq!(indent, "");
+    }
+
+    #[test]
+    fn keeps_line_numbers() {
+        let source = "\n    a = 1\n\n    b = 2\n  \n";
+        let (code, _) = dedent(source);
+        assert_eq!(code, "\na = 1\n\nb = 2\n\n");
+        assert_eq!(code.lines().count(), source.lines().count());
+    }
+
+    #[test]
+    fn empties_short_blank_lines() {
+        let (code, indent) = dedent("        a = 1\n  \n        b = 2");
+        assert_eq!(code, "a = 1\n\nb = 2");
+        assert_eq!(indent, "        ");
+    }
+
+    #[test]
+    fn only_removes_matching_tabs_and_spaces() {
+        let (code, indent) = dedent("\tif a:\n\t    b = 2\n");
+        assert_eq!(code, "if a:\n    b = 2\n");
+        assert_eq!(indent, "\t");
+
+        let (code, indent) = dedent("\ta = 1\n    b = 2\n");
+        assert_eq!(code, "\ta = 1\n    b = 2\n");
+        assert_eq!(indent, "");
+    }
+
+    #[test]
+    fn keeps_crlf_line_endings() {
+        let (code, indent) = dedent("    a = 1\r\n \r\n    b = 2\r\n");
+        assert_eq!(code, "a = 1\r\n\r\nb = 2\r\n");
+        assert_eq!(indent, "    ");
+    }
+
+    #[test]
+    fn treats_the_first_line_like_any_other() {
+        let (code, indent) = dedent("  a = 1");
+        assert_eq!(code, "a = 1");
+        assert_eq!(indent, "  ");
+    }
+
+    #[test]
+    fn preserves_columns_when_the_indent_is_added_back() {
+        let source = "\n    x = (1 +\n        2)\n";
+        let (code, indent) = dedent(source);
+        for (original, dedented) in source.lines().zip(code.lines()) {
+            if !dedented.is_empty() {
+                assert_eq!(format!("{indent}{dedented}"), original);
+            }
+        }
+    }
+
+    #[test]
+    fn leaves_blank_code_alone() {
+        assert_eq!(dedent("  \n\n"), ("  \n\n".to_string(), String::new()));
+        assert_eq!(dedent(""), (String::new(), String::new()));
+    }
+}
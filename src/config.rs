@@ -0,0 +1,472 @@
+#[cfg(feature = "python-module")]
+use std::collections::hash_map::DefaultHasher;
+use std::ffi::c_int;
+use std::hash::Hash;
+#[cfg(feature = "python-module")]
+use std::hash::Hasher;
+use std::str::FromStr;
+
+#[cfg(feature = "python-module")]
+use pyo3::pyclass::CompareOp;
+#[cfg(feature = "python-module")]
+use pyo3::types::PyDict;
+use pyo3::{ffi, pyclass};
+#[cfg(feature = "python-module")]
+use pyo3::{pymethods, IntoPy, PyAny, PyObject, PyResult, Python};
+
+#[cfg(feature = "python-module")]
+use crate::CreateInterpreterError;
+
+#[pyclass(frozen)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// The config for creating a new sub interpreter.
+///
+/// Configs can't be changed once created, use `from_dict` to create a modified copy.
+pub struct InterpreterConfig {
+    /// If this is `false` then the runtime will not support forking the process in any thread where
+    /// the sub-interpreter is currently active. Otherwise fork is unrestricted.
+    ///
+    /// Note that the subprocess module still works when fork is disallowed.
+    ///
+    /// NOTE:
+    /// It is probably not a good idea to enable this, the affects of forking are largely unknown
+    /// around the behaviour of the sub-interpreters and if that causes effectively an exploding
+    /// amount of threads.
+    ///
+    /// TL;DR: You probably do not want this.
+    #[pyo3(get)]
+    allow_fork: bool,
+    /// If this is `false` then the runtime will not support replacing the current process via exec
+    /// (e.g. os.execv()) in any thread where the sub-interpreter is currently active.
+    /// Otherwise exec is unrestricted.
+    ///
+    /// Note that the subprocess module still works when exec is disallowed.
+    ///
+    /// NOTE:
+    /// Like `allow_fork` you are probably asking for trouble, if you enable this; do so at your
+    /// own risk, the consequences of replacing the current process is unknown.
+    #[pyo3(get)]
+    allow_exec: bool,
+    /// If this is `false` then the sub-interpreter’s threading module won’t create threads.
+    /// Otherwise threads are allowed.
+    ///
+    /// *This is enabled by default.*
+    #[pyo3(get)]
+    allow_threads: bool,
+    /// If this is `false` then the sub-interpreter’s threading module won’t create daemon threads.
+    /// Otherwise daemon threads are allowed (as long as allow_threads is also enabled).
+    ///
+    /// *This is enabled by default.*
+    #[pyo3(get)]
+    allow_daemon_threads: bool,
+    /// If this is `true` then the sub-interpreter will use the main interpreter's memory
+    /// allocator instead of its own.
+    ///
+    /// This is required to load extension modules which do not support multiple
+    /// interpreters, but then the interpreter must share the main interpreter's GIL.
+    #[pyo3(get)]
+    use_main_obmalloc: bool,
+    /// If this is `true` then importing an extension module which does not support
+    /// multiple interpreters (i.e. single-phase init modules) raises an `ImportError`.
+    ///
+    /// This can only be disabled if `use_main_obmalloc` is enabled.
+    ///
+    /// *This is enabled by default.*
+    #[pyo3(get)]
+    check_multi_interp_extensions: bool,
+    /// Which GIL the sub-interpreter uses.
+    ///
+    /// *This is `GilMode::Own` by default.*
+    gil: GilMode,
+}
+
+impl InterpreterConfig {
+    /// The config for a fully isolated interpreter with its own GIL, the same as
+    /// Python's own default for new interpreters (`_PyInterpreterConfig_INIT`).
+    pub fn isolated() -> Self {
+        Self {
+            allow_fork: false,
+            allow_exec: false,
+            allow_threads: true,
+            allow_daemon_threads: false,
+            use_main_obmalloc: false,
+            check_multi_interp_extensions: true,
+            gil: GilMode::Own,
+        }
+    }
+
+    /// The config for an interpreter which shares the main interpreter's GIL and memory
+    /// allocator, like interpreters created before Python 3.12 (`_PyInterpreterConfig_LEGACY_INIT`).
+    pub fn legacy() -> Self {
+        Self {
+            allow_fork: true,
+            allow_exec: true,
+            allow_threads: true,
+            allow_daemon_threads: true,
+            use_main_obmalloc: true,
+            check_multi_interp_extensions: false,
+            gil: GilMode::Shared,
+        }
+    }
+
+    /// Creates a builder which starts from `InterpreterConfig::isolated()`.
+    pub fn builder() -> InterpreterConfigBuilder {
+        InterpreterConfigBuilder::from(Self::isolated())
+    }
+
+    /// Whether the interpreter can fork the process, see the field docs for more.
+    pub fn allow_fork(&self) -> bool {
+        self.allow_fork
+    }
+
+    /// Whether the interpreter can replace the process via exec.
+    pub fn allow_exec(&self) -> bool {
+        self.allow_exec
+    }
+
+    /// Whether the interpreter's `threading` module can create threads.
+    pub fn allow_threads(&self) -> bool {
+        self.allow_threads
+    }
+
+    /// Whether the interpreter's `threading` module can create daemon threads.
+    pub fn allow_daemon_threads(&self) -> bool {
+        self.allow_daemon_threads
+    }
+
+    /// Whether the interpreter uses the main interpreter's memory allocator.
+    pub fn use_main_obmalloc(&self) -> bool {
+        self.use_main_obmalloc
+    }
+
+    /// Whether importing single-phase init extension modules raises an `ImportError`.
+    pub fn check_multi_interp_extensions(&self) -> bool {
+        self.check_multi_interp_extensions
+    }
+
+    /// Which GIL the interpreter uses.
+    pub fn gil(&self) -> GilMode {
+        self.gil
+    }
+
+    /// Checks for combinations of options which Python would reject or which are unsafe.
+    pub(crate) fn validate(&self) -> Result<(), ConfigError> {
+        if !self.allow_threads && self.allow_daemon_threads {
+            return Err(ConfigError::DaemonThreadsWithoutThreads);
+        }
+        if !self.use_main_obmalloc && !self.check_multi_interp_extensions {
+            return Err(ConfigError::SinglePhaseInitWithOwnObmalloc);
+        }
+        if self.use_main_obmalloc && self.gil == GilMode::Own {
+            return Err(ConfigError::MainObmallocWithOwnGil);
+        }
+        Ok(())
+    }
+
+    /// The config as Python expects it to be given to `Py_NewInterpreterFromConfig`.
+    pub(crate) fn as_ffi(&self) -> ffi::PyInterpreterConfig {
+        ffi::PyInterpreterConfig {
+            use_main_obmalloc: self.use_main_obmalloc as c_int,
+            allow_fork: self.allow_fork as c_int,
+            allow_exec: self.allow_exec as c_int,
+            allow_threads: self.allow_threads as c_int,
+            allow_daemon_threads: self.allow_daemon_threads as c_int,
+            check_multi_interp_extensions: self.check_multi_interp_extensions as c_int,
+            gil: self.gil.as_ffi(),
+        }
+    }
+}
+
+#[cfg(feature = "python-module")]
+#[pymethods]
+impl InterpreterConfig {
+    #[staticmethod]
+    #[pyo3(name = "isolated")]
+    /// The config for a fully isolated interpreter with its own GIL.
+    ///
+    /// This is the default used by `create_interpreter`.
+    fn py_isolated() -> Self {
+        Self::isolated()
+    }
+
+    #[staticmethod]
+    #[pyo3(name = "legacy")]
+    /// The config for an interpreter which shares the main interpreter's GIL and
+    /// memory allocator, which allows importing any extension module.
+    fn py_legacy() -> Self {
+        Self::legacy()
+    }
+
+    #[staticmethod]
+    /// Creates a config from a dict, like the one returned by `to_dict`.
+    ///
+    /// Any options missing from the dict are taken from `InterpreterConfig.isolated()`.
+    /// Raises an `InterpreterConfigError` if the dict contains unknown options or the
+    /// combination of options is invalid.
+    fn from_dict(options: &PyDict) -> PyResult<Self> {
+        let mut config = Self::isolated();
+
+        for (key, value) in options.iter() {
+            let key = key.extract::<&str>()?;
+            match key {
+                "allow_fork" => config.allow_fork = value.extract()?,
+                "allow_exec" => config.allow_exec = value.extract()?,
+                "allow_threads" => config.allow_threads = value.extract()?,
+                "allow_daemon_threads" => config.allow_daemon_threads = value.extract()?,
+                "use_main_obmalloc" => config.use_main_obmalloc = value.extract()?,
+                "check_multi_interp_extensions" => {
+                    config.check_multi_interp_extensions = value.extract()?
+                }
+                "gil" => {
+                    config.gil = value
+                        .extract::<&str>()?
+                        .parse()
+                        .map_err(CreateInterpreterError::from)?
+                }
+                _ => {
+                    let err = ConfigError::UnknownOption(key.to_string());
+                    return Err(CreateInterpreterError::from(err).into());
+                }
+            }
+        }
+
+        config.validate().map_err(CreateInterpreterError::from)?;
+        Ok(config)
+    }
+
+    #[allow(clippy::wrong_self_convention)]
+    /// Returns the config as a dict of option names to values.
+    pub(crate) fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<&'py PyDict> {
+        let dict = PyDict::new(py);
+        dict.set_item("allow_fork", self.allow_fork)?;
+        dict.set_item("allow_exec", self.allow_exec)?;
+        dict.set_item("allow_threads", self.allow_threads)?;
+        dict.set_item("allow_daemon_threads", self.allow_daemon_threads)?;
+        dict.set_item("use_main_obmalloc", self.use_main_obmalloc)?;
+        dict.set_item(
+            "check_multi_interp_extensions",
+            self.check_multi_interp_extensions,
+        )?;
+        dict.set_item("gil", self.gil.as_str())?;
+        Ok(dict)
+    }
+
+    #[getter]
+    #[pyo3(name = "gil")]
+    /// Which GIL the interpreter uses, one of `"own"`, `"shared"` or `"default"`.
+    fn py_gil(&self) -> &'static str {
+        self.gil.as_str()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "InterpreterConfig(allow_fork={}, allow_exec={}, allow_threads={}, \
+            allow_daemon_threads={}, use_main_obmalloc={}, check_multi_interp_extensions={}, \
+            gil={:?})",
+            py_bool(self.allow_fork),
+            py_bool(self.allow_exec),
+            py_bool(self.allow_threads),
+            py_bool(self.allow_daemon_threads),
+            py_bool(self.use_main_obmalloc),
+            py_bool(self.check_multi_interp_extensions),
+            self.gil.as_str(),
+        )
+    }
+
+    fn __richcmp__(&self, other: &PyAny, op: CompareOp) -> PyObject {
+        let py = other.py();
+        let Ok(other) = other.extract::<Self>() else {
+            return py.NotImplemented();
+        };
+
+        match op {
+            CompareOp::Eq => (*self == other).into_py(py),
+            CompareOp::Ne => (*self != other).into_py(py),
+            _ => py.NotImplemented(),
+        }
+    }
+
+    fn __hash__(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+#[cfg(feature = "python-module")]
+fn py_bool(value: bool) -> &'static str {
+    if value {
+        "True"
+    } else {
+        "False"
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+/// Builds an `InterpreterConfig` one option at a time.
+///
+/// Any options which aren't set are left as they were in the config the builder
+/// started from, and the combination of options is only checked once it is built.
+///
+/// ```
+/// use subinterpreters::{GilMode, InterpreterConfig};
+///
+/// let config = InterpreterConfig::builder()
+///     .allow_threads(false)
+///     .gil(GilMode::Own)
+///     .build()
+///     .unwrap();
+/// assert!(!config.allow_threads());
+/// ```
+pub struct InterpreterConfigBuilder(InterpreterConfig);
+
+impl InterpreterConfigBuilder {
+    pub fn allow_fork(mut self, allow_fork: bool) -> Self {
+        self.0.allow_fork = allow_fork;
+        self
+    }
+
+    pub fn allow_exec(mut self, allow_exec: bool) -> Self {
+        self.0.allow_exec = allow_exec;
+        self
+    }
+
+    pub fn allow_threads(mut self, allow_threads: bool) -> Self {
+        self.0.allow_threads = allow_threads;
+        self
+    }
+
+    pub fn allow_daemon_threads(mut self, allow_daemon_threads: bool) -> Self {
+        self.0.allow_daemon_threads = allow_daemon_threads;
+        self
+    }
+
+    pub fn use_main_obmalloc(mut self, use_main_obmalloc: bool) -> Self {
+        self.0.use_main_obmalloc = use_main_obmalloc;
+        self
+    }
+
+    pub fn check_multi_interp_extensions(mut self, check_multi_interp_extensions: bool) -> Self {
+        self.0.check_multi_interp_extensions = check_multi_interp_extensions;
+        self
+    }
+
+    pub fn gil(mut self, gil: GilMode) -> Self {
+        self.0.gil = gil;
+        self
+    }
+
+    /// Builds the config, returning an error if the combination of options is invalid.
+    pub fn build(self) -> Result<InterpreterConfig, ConfigError> {
+        self.0.validate()?;
+        Ok(self.0)
+    }
+}
+
+impl From<InterpreterConfig> for InterpreterConfigBuilder {
+    /// Creates a builder which starts from the given config.
+    fn from(config: InterpreterConfig) -> Self {
+        Self(config)
+    }
+}
+
+#[cfg(feature = "python-module")]
+/// Config options given individually to `create_interpreter` or `create_pool`,
+/// which take priority over those of the config they were given.
+pub(crate) struct ConfigOptions<'a> {
+    pub(crate) allow_fork: Option<bool>,
+    pub(crate) allow_exec: Option<bool>,
+    pub(crate) allow_threads: Option<bool>,
+    pub(crate) allow_daemon_threads: Option<bool>,
+    pub(crate) use_main_obmalloc: Option<bool>,
+    pub(crate) check_multi_interp_extensions: Option<bool>,
+    pub(crate) gil: Option<&'a str>,
+}
+
+#[cfg(feature = "python-module")]
+impl ConfigOptions<'_> {
+    /// Applies the options to the config, checking that the result is valid.
+    pub(crate) fn apply(self, config: InterpreterConfig) -> Result<InterpreterConfig, ConfigError> {
+        let config = InterpreterConfig {
+            allow_fork: self.allow_fork.unwrap_or(config.allow_fork),
+            allow_exec: self.allow_exec.unwrap_or(config.allow_exec),
+            allow_threads: self.allow_threads.unwrap_or(config.allow_threads),
+            allow_daemon_threads: self
+                .allow_daemon_threads
+                .unwrap_or(config.allow_daemon_threads),
+            use_main_obmalloc: self.use_main_obmalloc.unwrap_or(config.use_main_obmalloc),
+            check_multi_interp_extensions: self
+                .check_multi_interp_extensions
+                .unwrap_or(config.check_multi_interp_extensions),
+            gil: self.gil.map(str::parse).transpose()?.unwrap_or(config.gil),
+        };
+
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// Which GIL a sub-interpreter uses.
+pub enum GilMode {
+    /// Python's default, which for sub-interpreters is currently the same as `Shared`.
+    Default,
+    /// The sub-interpreter shares the main interpreter's GIL, so it cannot run in
+    /// parallel with any other interpreter using the same GIL.
+    Shared,
+    /// The sub-interpreter has its own GIL.
+    Own,
+}
+
+impl GilMode {
+    #[cfg(feature = "python-module")]
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::Shared => "shared",
+            Self::Own => "own",
+        }
+    }
+
+    pub(crate) fn as_ffi(self) -> c_int {
+        match self {
+            Self::Default => ffi::PyInterpreterConfig_DEFAULT_GIL,
+            Self::Shared => ffi::PyInterpreterConfig_SHARED_GIL,
+            Self::Own => ffi::PyInterpreterConfig_OWN_GIL,
+        }
+    }
+}
+
+impl FromStr for GilMode {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Self::Default),
+            "shared" => Ok(Self::Shared),
+            "own" => Ok(Self::Own),
+            _ => Err(ConfigError::InvalidGil(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+/// An invalid combination of interpreter config options.
+pub enum ConfigError {
+    #[error("daemon threads cannot be enabled if `allow_threads` is `false`.")]
+    DaemonThreadsWithoutThreads,
+    #[error(
+        "`check_multi_interp_extensions` cannot be disabled unless `use_main_obmalloc` is enabled, \
+        interpreters with their own memory allocator do not support single-phase init extension modules."
+    )]
+    SinglePhaseInitWithOwnObmalloc,
+    #[error(
+        "`use_main_obmalloc` cannot be enabled when the interpreter has its own GIL, \
+        the main interpreter's memory allocator is only safe to use while holding the main GIL."
+    )]
+    MainObmallocWithOwnGil,
+    #[error("`gil` must be one of \"own\", \"shared\" or \"default\", not {0:?}.")]
+    InvalidGil(String),
+    #[error("{0:?} is not an interpreter config option.")]
+    UnknownOption(String),
+}
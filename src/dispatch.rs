@@ -0,0 +1,89 @@
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+
+use pyo3::{PyAny, PyObject, Python};
+
+type Callback = Box<dyn FnOnce(Python) + Send>;
+
+static DISPATCHER: OnceLock<Mutex<Sender<Callback>>> = OnceLock::new();
+
+/// Schedules the callback to run within the main interpreter.
+///
+/// Worker threads cannot safely acquire the main GIL themselves (their thread state
+/// belongs to a sub-interpreter), so instead callbacks are run in order by a dedicated
+/// dispatcher thread which only ever touches the main interpreter.
+pub(crate) fn call_soon<F>(callback: F)
+where
+    F: FnOnce(Python) + Send + 'static,
+{
+    let sender = DISPATCHER.get_or_init(|| {
+        let (tx, rx) = mpsc::channel::<Callback>();
+
+        thread::Builder::new()
+            .name("subinterpreters-dispatch".to_string())
+            .spawn(move || {
+                while let Ok(callback) = rx.recv() {
+                    Python::with_gil(|py| {
+                        callback(py);
+
+                        // Run anything else which is already waiting without
+                        // releasing the GIL in-between.
+                        for callback in rx.try_iter() {
+                            callback(py);
+                        }
+                    });
+                }
+            })
+            .expect("failed to spawn dispatcher thread");
+
+        Mutex::new(tx)
+    });
+
+    let _ = sender.lock().unwrap().send(Box::new(callback));
+}
+
+/// Waits for every callback which has already been scheduled to run.
+///
+/// This must be called without holding the main GIL.
+pub(crate) fn flush() {
+    if DISPATCHER.get().is_none() {
+        return;
+    }
+
+    let (tx, rx) = mpsc::channel();
+    call_soon(move |_py| {
+        let _ = tx.send(());
+    });
+    let _ = rx.recv();
+}
+
+/// A reference to an object owned by the main interpreter which can be moved between threads.
+///
+/// Unlike a plain `PyObject`, it is safe to drop this without holding the main GIL,
+/// as the reference is always released by the dispatcher thread.
+pub(crate) struct MainObject(Option<PyObject>);
+
+impl MainObject {
+    pub(crate) fn new(obj: PyObject) -> Self {
+        Self(Some(obj))
+    }
+
+    /// Gets the object, this should only be called within the main interpreter.
+    pub(crate) fn get<'py>(&'py self, py: Python<'py>) -> &'py PyAny {
+        self.0.as_ref().unwrap().as_ref(py)
+    }
+
+    /// Takes the object back out, this should only be called within the main interpreter.
+    pub(crate) fn into_inner(mut self) -> PyObject {
+        self.0.take().unwrap()
+    }
+}
+
+impl Drop for MainObject {
+    fn drop(&mut self) {
+        if let Some(obj) = self.0.take() {
+            call_soon(move |_py| drop(obj));
+        }
+    }
+}
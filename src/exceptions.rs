@@ -0,0 +1,293 @@
+// `create_exception!` checks for a cfg which newer compilers don't know about.
+#![allow(unexpected_cfgs)]
+
+use std::fmt;
+
+use pyo3::exceptions::{PyException, PySyntaxError};
+#[cfg(feature = "python-module")]
+use pyo3::types::PyModule;
+use pyo3::{create_exception, FromPyObject, IntoPy, PyAny, PyErr, PyObject, PyResult, Python};
+
+use crate::shareable::SharedValue;
+
+/// The maximum number of `__cause__`s captured along with an exception.
+///
+/// Causes can form a cycle, so the chain has to stop somewhere.
+const MAX_CAUSES: usize = 16;
+
+create_exception!(
+    subinterpreters,
+    SubInterpreterError,
+    PyException,
+    "The base class for all errors raised by this module."
+);
+create_exception!(
+    subinterpreters,
+    InterpreterConfigError,
+    SubInterpreterError,
+    "The interpreter config is invalid, or does not support the requested operation."
+);
+create_exception!(
+    subinterpreters,
+    InterpreterCreationError,
+    SubInterpreterError,
+    "Python failed to create a new sub-interpreter."
+);
+create_exception!(
+    subinterpreters,
+    InterpreterShutdownError,
+    SubInterpreterError,
+    "The interpreter (or pool) has already been shutdown."
+);
+create_exception!(
+    subinterpreters,
+    InterpreterBusyError,
+    SubInterpreterError,
+    "The interpreter is already running code for another thread."
+);
+create_exception!(
+    subinterpreters,
+    RemoteExecutionError,
+    SubInterpreterError,
+    "An exception was raised by code running within a sub-interpreter."
+);
+
+#[cfg(feature = "python-module")]
+/// Adds the module's exception classes to the module.
+pub(crate) fn register(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add("SubInterpreterError", py.get_type::<SubInterpreterError>())?;
+    m.add(
+        "InterpreterConfigError",
+        py.get_type::<InterpreterConfigError>(),
+    )?;
+    m.add(
+        "InterpreterCreationError",
+        py.get_type::<InterpreterCreationError>(),
+    )?;
+    m.add(
+        "InterpreterShutdownError",
+        py.get_type::<InterpreterShutdownError>(),
+    )?;
+    m.add(
+        "InterpreterBusyError",
+        py.get_type::<InterpreterBusyError>(),
+    )?;
+    m.add(
+        "RemoteExecutionError",
+        py.get_type::<RemoteExecutionError>(),
+    )?;
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+/// A copy of an exception raised within a sub-interpreter.
+///
+/// Exceptions hold onto objects owned by the interpreter which raised them (the
+/// traceback, frames, arguments, etc...), so they cannot be raised again by any other
+/// interpreter. Instead everything useful about them is copied out as plain data
+/// and a new `RemoteExecutionError` is raised in their place.
+///
+/// The exception's arguments are copied as a `SharedValue` too, as long as they are
+/// all shareable.
+pub struct RemoteError {
+    type_name: String,
+    module: String,
+    message: String,
+    traceback: String,
+    args: Option<SharedValue>,
+    location: Option<SyntaxLocation>,
+    cause: Option<Box<RemoteError>>,
+}
+
+#[derive(Debug, Clone)]
+/// Where within the source code a `SyntaxError` was raised.
+struct SyntaxLocation {
+    filename: Option<String>,
+    lineno: Option<usize>,
+    offset: Option<usize>,
+    text: Option<String>,
+}
+
+impl SyntaxLocation {
+    fn from_exception(exc: &PyAny) -> Option<Self> {
+        if !exc.is_instance_of::<PySyntaxError>() {
+            return None;
+        }
+
+        Some(Self {
+            filename: extract_attr(exc, "filename"),
+            lineno: extract_attr(exc, "lineno"),
+            offset: extract_attr(exc, "offset"),
+            text: extract_attr(exc, "text"),
+        })
+    }
+
+    fn into_attrs(self, py: Python) -> [(&'static str, PyObject); 4] {
+        [
+            ("filename", self.filename.into_py(py)),
+            ("lineno", self.lineno.into_py(py)),
+            ("offset", self.offset.into_py(py)),
+            ("text", self.text.into_py(py)),
+        ]
+    }
+}
+
+impl RemoteError {
+    /// Captures the error, this must be called by the interpreter which raised it.
+    ///
+    /// The result is `Shareable`, so can be returned from `Interpreter::with_gil`
+    /// and raised again within the calling interpreter by converting it into a `PyErr`.
+    pub fn capture(py: Python, err: PyErr) -> Self {
+        Self::from_exception(err.value(py), 0)
+    }
+
+    fn from_exception(exc: &PyAny, depth: usize) -> Self {
+        let ty = exc.get_type();
+
+        let type_name = ty
+            .getattr("__qualname__")
+            .and_then(|name| name.extract())
+            .unwrap_or_else(|_| "<unknown>".to_string());
+        let module = ty
+            .getattr("__module__")
+            .and_then(|module| module.extract())
+            .unwrap_or_else(|_| "<unknown>".to_string());
+        let message = exc
+            .str()
+            .map(|msg| msg.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let traceback = format_exception(exc).unwrap_or_default();
+        let args = exc
+            .getattr("args")
+            .ok()
+            .and_then(|args| SharedValue::extract_from(exc.py(), args).ok());
+        let location = SyntaxLocation::from_exception(exc);
+
+        let cause = match exc.getattr("__cause__") {
+            Ok(cause) if !cause.is_none() && depth < MAX_CAUSES => {
+                Some(Box::new(Self::from_exception(cause, depth + 1)))
+            }
+            _ => None,
+        };
+
+        Self {
+            type_name,
+            module,
+            message,
+            traceback,
+            args,
+            location,
+            cause,
+        }
+    }
+
+    /// The name of the exception type, including its module unless it is a builtin.
+    fn qualified_name(&self) -> String {
+        if self.module == "builtins" {
+            self.type_name.clone()
+        } else {
+            format!("{}.{}", self.module, self.type_name)
+        }
+    }
+
+    /// The name of the exception type, without its module.
+    pub fn type_name(&self) -> &str {
+        &self.type_name
+    }
+
+    /// The module the exception type was defined in.
+    pub fn module(&self) -> &str {
+        &self.module
+    }
+
+    /// The exception's message, as given by `str(exc)`.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The exception's formatted traceback.
+    pub fn traceback(&self) -> &str {
+        &self.traceback
+    }
+
+    /// The exception's arguments, or `None` if any of them cannot be shared.
+    pub fn args(&self) -> Option<&SharedValue> {
+        self.args.as_ref()
+    }
+
+    /// The exception's `__cause__`, if it had one.
+    pub fn cause(&self) -> Option<&RemoteError> {
+        self.cause.as_deref()
+    }
+
+    /// Creates the `RemoteExecutionError` to raise within the current interpreter.
+    fn into_err(self, py: Python) -> PyErr {
+        let err = RemoteExecutionError::new_err(self.to_string());
+        let value = err.value(py);
+
+        let attrs = [
+            ("type_name", self.type_name.into_py(py)),
+            ("module", self.module.into_py(py)),
+            ("message", self.message.into_py(py)),
+            ("traceback", self.traceback.into_py(py)),
+            ("remote_args", self.args.into_py(py)),
+        ];
+        // Syntax errors also keep where the error is, like a `SyntaxError` would.
+        let location = self.location.map(|location| location.into_attrs(py));
+        for (name, attr) in attrs.into_iter().chain(location.into_iter().flatten()) {
+            if let Err(e) = value.setattr(name, attr) {
+                e.print(py);
+            }
+        }
+
+        err.set_cause(py, self.cause.map(|cause| cause.into_err(py)));
+        err
+    }
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.qualified_name();
+        if self.message.is_empty() {
+            write!(f, "{name}")
+        } else {
+            write!(f, "{name}: {}", self.message)
+        }
+    }
+}
+
+impl std::error::Error for RemoteError {}
+
+impl From<RemoteError> for PyErr {
+    fn from(value: RemoteError) -> Self {
+        let traceback = format!(
+            "Traceback from the sub-interpreter:\n{}",
+            value.traceback.trim_end()
+        );
+
+        Python::with_gil(|py| {
+            let err = value.into_err(py);
+
+            // Notes are shown alongside the exception if it is never caught.
+            if let Err(e) = err.value(py).call_method1("add_note", (traceback,)) {
+                e.print(py);
+            }
+
+            err
+        })
+    }
+}
+
+/// Gets the attribute of the exception, treating it as `None` if it is missing or has the wrong type.
+fn extract_attr<'py, T: FromPyObject<'py>>(exc: &'py PyAny, name: &str) -> Option<T> {
+    exc.getattr(name).ok()?.extract().ok()
+}
+
+fn format_exception(exc: &PyAny) -> PyResult<String> {
+    let lines = exc
+        .py()
+        .import("traceback")?
+        .call_method1("format_exception", (exc,))?
+        .extract::<Vec<String>>()?;
+    Ok(lines.concat())
+}
@@ -0,0 +1,49 @@
+import os
+import threading
+from concurrent.futures import Executor
+
+
+class SubInterpreterExecutor(Executor):
+    """
+    A `concurrent.futures.Executor` which runs calls within a pool of sub-interpreters.
+
+    This is intended as a drop-in replacement for `ProcessPoolExecutor`, functions are
+    passed by name and so must be importable (i.e. not defined within `__main__`),
+    and their arguments and return values must be shareable.
+
+    Any extra keyword arguments are passed to `create_pool` when creating the interpreters.
+    """
+
+    def __init__(self, max_workers=None, **config):
+        from subinterpreters import create_pool
+
+        if max_workers is None:
+            max_workers = os.cpu_count() or 1
+        if max_workers <= 0:
+            raise ValueError("max_workers must be greater than 0")
+
+        self._pool = create_pool(max_workers, **config)
+        self._pending = set()
+        self._lock = threading.Lock()
+
+    def submit(self, fn, /, *args, **kwargs):
+        with self._lock:
+            future = self._pool.call(fn, *args, **kwargs)
+            self._pending.add(future)
+
+        future.add_done_callback(self._discard)
+        return future
+
+    def shutdown(self, wait=True, *, cancel_futures=False):
+        if cancel_futures:
+            with self._lock:
+                pending = list(self._pending)
+
+            for future in pending:
+                future.cancel()
+
+        self._pool.shutdown(wait)
+
+    def _discard(self, future):
+        with self._lock:
+            self._pending.discard(future)
@@ -0,0 +1,209 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::types::{PyCFunction, PyDict, PyTuple};
+use pyo3::{pyfunction, wrap_pyfunction, PyAny, PyErr, PyObject, PyResult, Python};
+
+use crate::dispatch::{self, MainObject};
+use crate::exceptions::RemoteError;
+use crate::shareable::{ShareError, SharedValue};
+use crate::transport::Transport;
+
+/// The result of a job, with any error already captured by the sub-interpreter.
+///
+/// A result which couldn't be shared is kept as a `ShareError`, so it is raised as a
+/// `TypeError` rather than a `RemoteExecutionError`.
+pub(crate) type JobResult = Result<Result<SharedValue, ShareError>, RemoteError>;
+
+/// A future within the main interpreter which is waiting on a job to complete.
+pub(crate) struct PendingFuture {
+    kind: FutureKind,
+    /// Set once the future has been cancelled.
+    cancelled: Arc<AtomicBool>,
+}
+
+enum FutureKind {
+    /// A `concurrent.futures.Future`, which can be resolved from any thread.
+    Concurrent(Arc<MainObject>),
+    /// An `asyncio.Future`, which must be resolved by its own event loop.
+    Asyncio {
+        event_loop: MainObject,
+        future: MainObject,
+    },
+}
+
+impl PendingFuture {
+    /// Creates a new `concurrent.futures.Future`.
+    pub(crate) fn concurrent(py: Python) -> PyResult<(Self, PyObject)> {
+        let future: PyObject = py
+            .import("concurrent.futures")?
+            .getattr("Future")?
+            .call0()?
+            .into();
+
+        let kind = FutureKind::Concurrent(Arc::new(MainObject::new(future.clone_ref(py))));
+        Ok((Self::new(py, kind, future.as_ref(py))?, future))
+    }
+
+    /// Creates a new `asyncio.Future` attached to the currently running event loop.
+    ///
+    /// Returns an error if there is no running event loop.
+    pub(crate) fn asyncio(py: Python) -> PyResult<(Self, PyObject)> {
+        let event_loop = py.import("asyncio")?.call_method0("get_running_loop")?;
+        let future: PyObject = event_loop.call_method0("create_future")?.into();
+
+        let kind = FutureKind::Asyncio {
+            event_loop: MainObject::new(event_loop.into()),
+            future: MainObject::new(future.clone_ref(py)),
+        };
+        Ok((Self::new(py, kind, future.as_ref(py))?, future))
+    }
+
+    fn new(py: Python, kind: FutureKind, future: &PyAny) -> PyResult<Self> {
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        // Lets workers skip the job without needing the main GIL to check the future.
+        let flag = cancelled.clone();
+        let on_done = PyCFunction::new_closure(
+            py,
+            Some("on_done\0"),
+            None,
+            move |args: &PyTuple, _kwargs: Option<&PyDict>| -> PyResult<()> {
+                if args.get_item(0)?.call_method0("cancelled")?.is_true()? {
+                    flag.store(true, Ordering::SeqCst);
+                }
+                Ok(())
+            },
+        )?;
+        future.call_method1("add_done_callback", (on_done,))?;
+
+        Ok(Self { kind, cancelled })
+    }
+
+    /// Gets the handle which marks the future as running once its job starts.
+    pub(crate) fn start_handle(&self) -> FutureStart {
+        let future = match &self.kind {
+            FutureKind::Concurrent(future) => Some(future.clone()),
+            FutureKind::Asyncio { .. } => None,
+        };
+
+        FutureStart {
+            future,
+            cancelled: self.cancelled.clone(),
+        }
+    }
+
+    /// Resolves the future with the result of the job, which was copied out of the
+    /// sub-interpreter using `transport`.
+    ///
+    /// This can be called from any thread, the future itself is always resolved
+    /// within the main interpreter.
+    pub(crate) fn resolve(self, result: JobResult, transport: Transport) {
+        dispatch::call_soon(move |py| {
+            let result = split_result(py, result, transport);
+            let outcome = match self.kind {
+                FutureKind::Concurrent(future) => {
+                    resolve_future(future.get(py), result.0, result.1.as_ref(py))
+                }
+                FutureKind::Asyncio { event_loop, future } => {
+                    call_soon_threadsafe(py, event_loop.into_inner(), future.into_inner(), result)
+                }
+            };
+
+            if let Err(e) = outcome {
+                e.print(py);
+            }
+        });
+    }
+}
+
+/// Marks a job's future as running once a worker picks the job up, like an `Executor` does.
+pub(crate) struct FutureStart {
+    /// `asyncio` futures don't have a running state, so only need checking for cancellation.
+    future: Option<Arc<MainObject>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl FutureStart {
+    /// Marks the future as running, returning `false` if it has been cancelled instead,
+    /// in which case the job must be skipped.
+    ///
+    /// Once this returns `true`, the future can no longer be cancelled. This must be called
+    /// without holding the GIL of any interpreter, since it waits on the main one.
+    pub(crate) fn start(self) -> bool {
+        if self.cancelled.load(Ordering::SeqCst) {
+            return false;
+        }
+        let Some(future) = self.future else {
+            return true;
+        };
+
+        let (tx, rx) = mpsc::channel();
+        dispatch::call_soon(move |py| {
+            let running = future
+                .get(py)
+                .call_method0("set_running_or_notify_cancel")
+                .and_then(PyAny::is_true);
+
+            let _ = tx.send(running.unwrap_or_else(|e| {
+                e.print(py);
+                false
+            }));
+        });
+        rx.recv().unwrap_or(false)
+    }
+}
+
+/// Schedules the future to be resolved by its event loop, which may be running on any thread.
+fn call_soon_threadsafe(
+    py: Python,
+    event_loop: PyObject,
+    future: PyObject,
+    result: (PyObject, PyObject),
+) -> PyResult<()> {
+    let (value, error) = result;
+    let callback = wrap_pyfunction!(resolve_future, py)?;
+
+    let result =
+        event_loop.call_method1(py, "call_soon_threadsafe", (callback, future, value, error));
+
+    // The event loop may have been closed while the job was running,
+    // in which case there is nothing left waiting on the future.
+    match result {
+        Err(e) if e.is_instance_of::<PyRuntimeError>(py) => Ok(()),
+        other => other.map(drop),
+    }
+}
+
+/// Splits the result into the future's value and exception, one of which is `None`.
+///
+/// The value is rebuilt within the main interpreter, so failing to unpickle it
+/// becomes the future's exception.
+fn split_result(py: Python, result: JobResult, transport: Transport) -> (PyObject, PyObject) {
+    let result = result
+        .map_err(PyErr::from)
+        .and_then(|value| value.map_err(PyErr::from))
+        .and_then(|value| transport.load(py, value));
+
+    match result {
+        Ok(value) => (value, py.None()),
+        Err(err) => (py.None(), err.into_value(py).into()),
+    }
+}
+
+#[pyfunction]
+/// Sets the result or exception of the future, unless it has already been cancelled.
+fn resolve_future(future: &PyAny, value: PyObject, error: &PyAny) -> PyResult<()> {
+    if future.call_method0("done")?.is_true()? {
+        return Ok(());
+    }
+
+    if error.is_none() {
+        future.call_method1("set_result", (value,))?;
+    } else {
+        future.call_method1("set_exception", (error,))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,370 @@
+use std::ffi::CStr;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use pyo3::{ffi, GILPool, PyErr, Python};
+
+use crate::exceptions::{InterpreterConfigError, InterpreterCreationError};
+use crate::interrupt::Interrupt;
+use crate::shareable::Shareable;
+use crate::{ConfigError, InterpreterConfig};
+
+#[derive(Debug, thiserror::Error)]
+/// A error which occurred while creating the interpreter.
+pub enum CreateInterpreterError {
+    #[error(transparent)]
+    ConfigError(#[from] ConfigError),
+    #[error("a Python interpreter has not yet been initialised and or is not running.")]
+    InitialisationError,
+    #[error("{0}")]
+    Other(String),
+}
+
+impl From<CreateInterpreterError> for PyErr {
+    fn from(value: CreateInterpreterError) -> Self {
+        match value {
+            CreateInterpreterError::ConfigError(_) => {
+                InterpreterConfigError::new_err(value.to_string())
+            }
+            CreateInterpreterError::InitialisationError | CreateInterpreterError::Other(_) => {
+                InterpreterCreationError::new_err(value.to_string())
+            }
+        }
+    }
+}
+
+/// A wrapper around a currently active sub-interpreter.
+///
+/// Once this is dropped, the interpreter will be shutdown.
+///
+/// ```no_run
+/// use pyo3::Python;
+/// use subinterpreters::{Interpreter, InterpreterConfig};
+///
+/// // Interpreters are created from within another interpreter, like the main one.
+/// let config = InterpreterConfig::isolated();
+/// let interpreter = Python::with_gil(|py| Interpreter::create(py, config))?;
+///
+/// let answer = interpreter.with_gil(|py| {
+///     let answer = py.eval("6 * 7", None, None).and_then(|answer| answer.extract::<i64>());
+///     // Python exceptions can't leave the interpreter either.
+///     answer.map_err(|err| err.to_string())
+/// })?;
+/// assert_eq!(answer, 42);
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+pub struct Interpreter {
+    inner: *mut ffi::PyThreadState,
+    id: i64,
+    interrupt: Arc<Interrupt>,
+}
+
+impl Interpreter {
+    /// Creates a new sub-interpreter using the given config.
+    ///
+    /// Returns an error if the interpreter config is invalid or
+    /// Python failed to create the interpreter.
+    ///
+    /// The interpreter is created from within the interpreter whose GIL `py` is for,
+    /// which is still held once this returns.
+    pub fn create(
+        _py: Python<'_>,
+        config: InterpreterConfig,
+    ) -> Result<Self, CreateInterpreterError> {
+        config.validate()?;
+
+        // SAFETY:
+        // This method simply wraps the internal calls to cpython, and should handle
+        // all operations correctly. The GIL token means there is a current thread state.
+        unsafe { Self::create_internal(config.as_ffi()) }
+    }
+
+    /// The ID Python gave the interpreter.
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    #[cfg(feature = "python-module")]
+    /// The Python identifier of the thread which created the interpreter.
+    pub(crate) fn thread(&self) -> Option<u64> {
+        self.interrupt.thread()
+    }
+
+    #[cfg(feature = "python-module")]
+    pub(crate) fn interrupt(&self) -> &Arc<Interrupt> {
+        &self.interrupt
+    }
+
+    #[cfg(feature = "python-module")]
+    pub(crate) fn is_valid(&self) -> bool {
+        !self.inner.is_null()
+    }
+
+    /// Shuts down the interpreter, this does nothing if it has already been shutdown.
+    pub fn shutdown(&mut self) {
+        if self.inner.is_null() {
+            return;
+        }
+
+        // Temporarily set the thread state to the `inner` state
+        // so we can shutdown the interpreter.
+        //
+        // The current thread state may be null if this is a worker thread.
+        unsafe {
+            // Threads interrupting the interpreter need its GIL to finish, which may be
+            // the one the caller is holding, so it is released while waiting for them.
+            let tmp_state = ffi::PyThreadState_Swap(std::ptr::null_mut());
+            self.interrupt.disable();
+
+            ffi::PyThreadState_Swap(self.inner);
+            ffi::Py_EndInterpreter(self.inner);
+            ffi::PyThreadState_Swap(tmp_state);
+        }
+
+        // The thread state was freed along with the interpreter.
+        self.inner = std::ptr::null_mut();
+    }
+
+    /// Runs the given function with the sub-interpreter set as the active interpreter.
+    ///
+    /// The function is given a `SubPython` token, and any Python objects it creates are
+    /// tied to that token's lifetime, so they can't be returned or smuggled out into
+    /// another interpreter's scope. Instead the result must be `Shareable`, which
+    /// means it contains no Python objects at all.
+    ///
+    /// ```compile_fail
+    /// # use pyo3::Python;
+    /// # use subinterpreters::{Interpreter, InterpreterConfig};
+    /// # let interpreter = Python::with_gil(|py| Interpreter::create(py, InterpreterConfig::isolated())).unwrap();
+    /// // `&PyAny` can't outlive the closure, and `Py<PyAny>` isn't `Shareable`.
+    /// let leaked = interpreter.with_gil(|py| py.eval("object()", None, None).unwrap());
+    /// ```
+    ///
+    /// `f` must also be `Send`, which stops it from capturing objects belonging to the
+    /// interpreter it was called from. `Py<T>` handles are `Send` however, so they
+    /// must still never be moved between interpreters by hand.
+    ///
+    /// `Py<T>` handles also must never be cloned or dropped without holding the GIL while
+    /// sub-interpreters are in use. pyo3 queues those reference count changes for the next
+    /// time any GIL is acquired, which may be this interpreter's, and an object freed by
+    /// the wrong interpreter is undefined behaviour.
+    ///
+    /// This can be called from any thread, whether or not it is holding another
+    /// interpreter's GIL. Any GIL held is released while `f` runs and acquired again afterwards.
+    ///
+    /// Panics if the interpreter has been shutdown.
+    pub fn with_gil<F, R>(&self, f: F) -> R
+    where
+        F: for<'py> FnOnce(SubPython<'py>) -> R + Send,
+        R: Shareable,
+    {
+        self.scope(|py| {
+            f(SubPython {
+                py,
+                interpreter: self,
+            })
+        })
+    }
+
+    /// Like `with_gil`, but gives `f` the GIL token directly and doesn't check its result.
+    ///
+    /// # Safety
+    ///
+    /// Python objects must not be returned from `f`, they belong to this interpreter and
+    /// using or dropping them within any other interpreter is undefined behaviour.
+    ///
+    /// Panics if the interpreter has been shutdown.
+    pub unsafe fn with<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(Python) -> T,
+    {
+        self.scope(f)
+    }
+
+    /// Runs the given function with the sub-interpreter set as the active interpreter.
+    ///
+    /// Any Python objects created within `f` are released before the previous
+    /// interpreter is restored, so they are always freed by the interpreter which owns them.
+    /// The caller is responsible for not letting any other objects escape.
+    ///
+    /// The `GILPool` created here also applies any reference count changes pyo3 queued
+    /// while no GIL was held, which is why `Py<T>` handles must never be cloned or dropped
+    /// without holding their interpreter's GIL (see `with_gil`).
+    ///
+    /// The previous interpreter is restored even if `f` panics.
+    pub(crate) fn scope<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(Python) -> T,
+    {
+        assert!(!self.inner.is_null());
+
+        // SAFETY:
+        // The sub-interpreter's thread state is only ever used by one thread at a time,
+        // and the guard restores the previous one however `f` exits.
+        unsafe {
+            let guard = ScopeGuard {
+                previous: ffi::PyThreadState_Swap(self.inner),
+                pool: ManuallyDrop::new(GILPool::new()),
+            };
+            self.interrupt.allow(|| f(guard.pool.python()))
+        }
+    }
+
+    unsafe fn create_internal(
+        config: ffi::PyInterpreterConfig,
+    ) -> Result<Self, CreateInterpreterError> {
+        if ffi::Py_IsInitialized() == 0 {
+            return Err(CreateInterpreterError::InitialisationError);
+        }
+
+        // Get the current GIL thread state, which the caller's GIL token guarantees exists.
+        let existing_state = ffi::PyThreadState_Get();
+
+        let mut state: *mut ffi::PyThreadState = std::ptr::null_mut();
+
+        // The `Py_NewInterpreterFromConfig` method replaces/swaps the current thread state.
+        // And also set the passed `state` to be the new thread state.
+        let status = ffi::Py_NewInterpreterFromConfig(&mut state as *mut _, &config as *const _);
+
+        // The new interpreter is still active at this point.
+        let created = (!state.is_null()).then(|| {
+            let interp = ffi::PyInterpreterState_Get();
+
+            // Python identifies thread states by the thread which created them, which is this one.
+            let pool = GILPool::new();
+            let thread = thread_ident(pool.python());
+            drop(pool);
+
+            (
+                ffi::PyInterpreterState_GetID(interp),
+                Interrupt::new(interp, thread),
+            )
+        });
+
+        // To avoid this behaviour as mentioned above, we will swap the old state back.
+        // This means any operations in this thread stay on the original state.
+        ffi::PyThreadState_Swap(existing_state);
+
+        if ffi::PyStatus_Exception(status) != 0 {
+            let msg = CStr::from_ptr(status.err_msg).to_str().unwrap_or("Unknown");
+            return Err(CreateInterpreterError::Other(msg.to_string()));
+        }
+
+        let (id, interrupt) = created.expect(
+            "thread state was none after Python returned successful response, something is very wrong.",
+        );
+
+        Ok(Self {
+            inner: state,
+            id,
+            interrupt: Arc::new(interrupt),
+        })
+    }
+}
+
+unsafe impl Send for Interpreter {}
+
+/// Switches back to the previous thread state once an `Interpreter::scope` has finished,
+/// first releasing the objects created within it.
+struct ScopeGuard {
+    previous: *mut ffi::PyThreadState,
+    pool: ManuallyDrop<GILPool>,
+}
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        // SAFETY:
+        // The sub-interpreter the pool was created within is still active.
+        unsafe {
+            ManuallyDrop::drop(&mut self.pool);
+            ffi::PyThreadState_Swap(self.previous);
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+/// A GIL token for a sub-interpreter, given to the function passed to `Interpreter::with_gil`.
+///
+/// This dereferences to pyo3's `Python` token, so it can be used the same way.
+pub struct SubPython<'py> {
+    py: Python<'py>,
+    interpreter: &'py Interpreter,
+}
+
+impl<'py> SubPython<'py> {
+    /// The pyo3 GIL token for the interpreter.
+    pub fn python(self) -> Python<'py> {
+        self.py
+    }
+
+    /// The interpreter which is currently active.
+    pub fn interpreter(self) -> &'py Interpreter {
+        self.interpreter
+    }
+}
+
+impl<'py> Deref for SubPython<'py> {
+    type Target = Python<'py>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.py
+    }
+}
+
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        self.shutdown()
+    }
+}
+
+#[cfg(feature = "python-module")]
+/// Returns `true` if the currently active interpreter is the main interpreter.
+///
+/// This module (and therefore any of its classes) can only be imported by the main
+/// interpreter, so sub-interpreters must be given plain Python objects instead.
+pub(crate) fn is_main_interpreter() -> bool {
+    unsafe { ffi::PyInterpreterState_Get() == ffi::PyInterpreterState_Main() }
+}
+
+/// Gets the identifier Python's `threading` module uses for the current thread.
+pub(crate) fn thread_ident(py: Python) -> Option<u64> {
+    let ident = py
+        .import("_thread")
+        .and_then(|thread| thread.call_method0("get_ident"))
+        .and_then(|ident| ident.extract());
+    ident.ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+
+    use pyo3::{ffi, Python};
+
+    use super::Interpreter;
+    use crate::InterpreterConfig;
+
+    #[test]
+    fn restores_the_previous_interpreter_after_a_panic() {
+        Python::with_gil(|py| {
+            let interpreter = Interpreter::create(py, InterpreterConfig::isolated()).unwrap();
+
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                interpreter.with_gil(|_py| -> () { panic!("panicked within the sub-interpreter") })
+            }));
+            assert!(result.is_err());
+
+            let is_main =
+                unsafe { ffi::PyInterpreterState_Get() == ffi::PyInterpreterState_Main() };
+            assert!(is_main);
+
+            let answer = interpreter.with_gil(|py| {
+                py.eval("6 * 7", None, None)
+                    .and_then(|answer| answer.extract::<i64>())
+                    .ok()
+            });
+            assert_eq!(answer, Some(42));
+        });
+    }
+}
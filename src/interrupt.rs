@@ -0,0 +1,391 @@
+use std::ffi::c_long;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+
+use pyo3::ffi;
+
+#[cfg(feature = "python-module")]
+use std::ffi::c_ulong;
+#[cfg(feature = "python-module")]
+use std::io;
+#[cfg(feature = "python-module")]
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+#[cfg(feature = "python-module")]
+use std::sync::{Arc, Weak};
+#[cfg(feature = "python-module")]
+use std::thread;
+#[cfg(feature = "python-module")]
+use std::time::Duration;
+
+#[cfg(feature = "python-module")]
+use pyo3::{PyResult, Python};
+
+#[cfg(feature = "python-module")]
+extern "C" {
+    // Part of Python's stable ABI, but not exposed by `pyo3::ffi`.
+    fn PyThread_get_thread_ident() -> c_ulong;
+}
+
+#[cfg(feature = "python-module")]
+#[derive(Debug, Copy, Clone)]
+/// The exceptions which can be raised within a sub-interpreter to interrupt it.
+pub(crate) enum Signal {
+    /// Raises a `KeyboardInterrupt`, like pressing `Ctrl+C` would.
+    Interrupt,
+    /// Raises a `KeyboardInterrupt` once the code has run for too long, rather than a
+    /// `TimeoutError` which `except Exception:` would catch.
+    Timeout,
+}
+
+#[cfg(feature = "python-module")]
+impl Signal {
+    fn exception(self) -> *mut ffi::PyObject {
+        // SAFETY:
+        // The builtin exception types are shared by every interpreter.
+        unsafe {
+            match self {
+                Self::Interrupt | Self::Timeout => ffi::PyExc_KeyboardInterrupt,
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct InterruptState {
+    /// `false` once the interpreter is being shutdown.
+    enabled: bool,
+    /// The number of threads currently interrupting the interpreter.
+    active: usize,
+}
+
+#[derive(Debug)]
+/// Lets other threads raise an exception within the code a sub-interpreter is running.
+///
+/// Python only checks for these exceptions between bytecode instructions, so code
+/// blocked within a call into C (e.g. a long `time.sleep`) is only interrupted once
+/// that call returns.
+pub(crate) struct Interrupt {
+    /// Only needed to raise exceptions, which only the Python module does.
+    #[cfg_attr(not(feature = "python-module"), allow(dead_code))]
+    interp: *mut ffi::PyInterpreterState,
+    /// The identifier of the thread which created the interpreter's thread state,
+    /// which is how Python finds the thread state to raise the exception in.
+    thread: Option<u64>,
+    /// `true` while the interpreter is running code which can be interrupted.
+    ///
+    /// This is only ever read or written while holding the interpreter's GIL.
+    running: AtomicBool,
+    state: Mutex<InterruptState>,
+    idle: Condvar,
+}
+
+// SAFETY:
+// The interpreter pointer is only used while the interpreter is alive, which
+// `disable` makes sure of before the interpreter is shutdown.
+unsafe impl Send for Interrupt {}
+unsafe impl Sync for Interrupt {}
+
+impl Interrupt {
+    pub(crate) fn new(interp: *mut ffi::PyInterpreterState, thread: Option<u64>) -> Self {
+        Self {
+            interp,
+            thread,
+            running: AtomicBool::new(false),
+            state: Mutex::new(InterruptState {
+                enabled: true,
+                active: 0,
+            }),
+            idle: Condvar::new(),
+        }
+    }
+
+    #[cfg(feature = "python-module")]
+    /// The Python identifier of the thread which created the interpreter.
+    pub(crate) fn thread(&self) -> Option<u64> {
+        self.thread
+    }
+
+    /// Allows the code run by `f` to be interrupted.
+    ///
+    /// This must be called from within the interpreter.
+    pub(crate) fn allow<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.running.store(true, Ordering::SeqCst);
+        let _running = Running(self);
+        f()
+    }
+
+    #[cfg(feature = "python-module")]
+    /// Raises the exception within the code the interpreter is running.
+    ///
+    /// Returns `false` if the interpreter isn't running anything.
+    ///
+    /// This must be called without holding the GIL of any interpreter.
+    pub(crate) fn interrupt(&self, signal: Signal) -> bool {
+        self.interrupt_if(signal, || true)
+    }
+
+    #[cfg(feature = "python-module")]
+    /// Like `interrupt`, but only if `condition` returns `true`.
+    ///
+    /// The condition is checked while holding the interpreter's GIL, so the code
+    /// the interpreter is running can't finish meanwhile.
+    pub(crate) fn interrupt_if(
+        &self,
+        signal: Signal,
+        condition: impl FnOnce() -> bool + Send,
+    ) -> bool {
+        let Some(thread) = self.thread else {
+            return false;
+        };
+
+        {
+            let mut state = self.state.lock().unwrap();
+            if !state.enabled {
+                return false;
+            }
+            state.active += 1;
+        }
+
+        // Python finds the thread state to raise the exception in by the identifier of the
+        // thread which created it, stopping at the first match, and the temporary thread state
+        // used to raise it is the first one checked. So the thread which created the
+        // interpreter (e.g. an inline interpreter being run by another thread) has to use a
+        // helper thread instead, whose identifier can't match while this thread is alive.
+        //
+        // SAFETY:
+        // Getting the current thread's identifier doesn't need the GIL.
+        let interrupted = if unsafe { PyThread_get_thread_ident() } as u64 == thread {
+            thread::scope(|scope| {
+                let helper = scope.spawn(|| self.raise(thread, signal, condition));
+                helper.join().unwrap_or(false)
+            })
+        } else {
+            self.raise(thread, signal, condition)
+        };
+
+        let mut state = self.state.lock().unwrap();
+        state.active -= 1;
+        if state.active == 0 {
+            self.idle.notify_all();
+        }
+
+        interrupted
+    }
+
+    #[cfg(feature = "python-module")]
+    /// Raises the exception within the given thread's thread state, if `condition` returns
+    /// `true`, using a temporary thread state for the current thread.
+    fn raise(&self, thread: u64, signal: Signal, condition: impl FnOnce() -> bool) -> bool {
+        // SAFETY:
+        // The interpreter can't be shutdown until `active` is back to zero, and the
+        // temporary thread state is only used by this thread.
+        unsafe {
+            let tstate = ffi::PyThreadState_New(self.interp);
+            let previous = ffi::PyThreadState_Swap(tstate);
+            debug_assert!(previous.is_null(), "interrupt called while holding a GIL");
+
+            let interrupted = self.running.load(Ordering::SeqCst)
+                && condition()
+                && ffi::PyThreadState_SetAsyncExc(thread as c_long, signal.exception()) > 0;
+
+            ffi::PyThreadState_Clear(tstate);
+            ffi::PyThreadState_DeleteCurrent();
+            interrupted
+        }
+    }
+
+    /// Stops the interpreter from being interrupted, waiting for any threads which
+    /// are already interrupting it.
+    ///
+    /// This must be called before the interpreter is shutdown, without holding
+    /// the GIL of any interpreter.
+    pub(crate) fn disable(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.enabled = false;
+        drop(
+            self.idle
+                .wait_while(state, |state| state.active > 0)
+                .unwrap(),
+        );
+    }
+}
+
+/// Stops an `Interrupt` from interrupting the interpreter once the code it allowed to be
+/// interrupted has finished, even if it panicked.
+struct Running<'a>(&'a Interrupt);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.running.store(false, Ordering::SeqCst);
+
+        // An interrupt which arrived after the code finished must not be left
+        // for whatever runs next.
+        if let Some(thread) = self.0.thread {
+            unsafe { ffi::PyThreadState_SetAsyncExc(thread as c_long, std::ptr::null_mut()) };
+        }
+    }
+}
+
+#[cfg(feature = "python-module")]
+/// Raises any exception which another thread has raised within the current thread,
+/// such as by `Interrupt::interrupt`.
+///
+/// Python only checks for these while it is running Python code, so anything which blocks
+/// outside of it for a long time (e.g. waiting on a channel) has to check for them itself.
+pub(crate) fn check_pending(py: Python) -> PyResult<()> {
+    // Python checks for them before running any code object.
+    py.eval("None", None, None).map(drop)
+}
+
+#[cfg(feature = "python-module")]
+#[derive(Debug, Default)]
+struct WatchdogState {
+    finished: AtomicBool,
+    fired: AtomicBool,
+}
+
+#[cfg(feature = "python-module")]
+/// How often the watchdog interrupts the code again once it has timed out, in case the
+/// code caught the exception and carried on.
+const WATCHDOG_REPEAT: Duration = Duration::from_millis(100);
+
+#[cfg(feature = "python-module")]
+/// Interrupts the code an interpreter is running if it runs for too long, and keeps
+/// interrupting it until it finishes.
+pub(crate) struct Watchdog {
+    _stop: Sender<()>,
+    state: Arc<WatchdogState>,
+}
+
+#[cfg(feature = "python-module")]
+impl Watchdog {
+    /// Starts timing the code the interpreter is about to run.
+    ///
+    /// The watchdog only holds a weak reference to the interpreter's `Interrupt`, which
+    /// stops interrupting the interpreter once it has been shutdown.
+    pub(crate) fn start(interrupt: Weak<Interrupt>, timeout: Duration) -> io::Result<Self> {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let state = Arc::new(WatchdogState::default());
+
+        let watched = state.clone();
+        thread::Builder::new()
+            .name("subinterpreter-watchdog".to_string())
+            .spawn(move || {
+                let mut wait = timeout;
+
+                // Stops once `finish` drops the sender.
+                while stopped.recv_timeout(wait) == Err(RecvTimeoutError::Timeout) {
+                    let Some(interrupt) = interrupt.upgrade() else {
+                        return;
+                    };
+
+                    interrupt.interrupt_if(Signal::Timeout, || {
+                        if watched.finished.load(Ordering::SeqCst) {
+                            return false;
+                        }
+                        watched.fired.store(true, Ordering::SeqCst);
+                        true
+                    });
+                    wait = WATCHDOG_REPEAT;
+                }
+            })?;
+
+        Ok(Self { _stop: stop, state })
+    }
+
+    /// Stops the watchdog, returning `true` if it interrupted the code.
+    ///
+    /// This must be called from within the interpreter.
+    pub(crate) fn finish(self) -> bool {
+        self.state.finished.store(true, Ordering::SeqCst);
+        self.state.fired.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(all(test, feature = "python-module"))]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use pyo3::exceptions::PyKeyboardInterrupt;
+    use pyo3::Python;
+
+    use super::{Signal, Watchdog};
+    use crate::{Interpreter, InterpreterConfig};
+
+    /// Interrupts the interpreter until something it runs has been interrupted.
+    fn interrupt_until_running(interrupt: &super::Interrupt) {
+        while !interrupt.interrupt(Signal::Interrupt) {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn interrupts_running_code() {
+        let interpreter =
+            Python::with_gil(|py| Interpreter::create(py, InterpreterConfig::isolated())).unwrap();
+        let interrupt = interpreter.interrupt().clone();
+
+        let interrupted = interpreter.scope(|py| {
+            thread::scope(|scope| {
+                scope.spawn(|| interrupt_until_running(&interrupt));
+                let err = py.run("while True: pass", None, None).unwrap_err();
+                err.is_instance_of::<PyKeyboardInterrupt>(py)
+            })
+        });
+        assert!(interrupted);
+
+        // Nothing is left pending for the next code to run.
+        assert!(interpreter.scope(|py| py.run("pass", None, None).is_ok()));
+    }
+
+    #[test]
+    fn interrupts_from_the_thread_which_created_the_interpreter() {
+        let interpreter =
+            Python::with_gil(|py| Interpreter::create(py, InterpreterConfig::isolated())).unwrap();
+        let interrupt = interpreter.interrupt().clone();
+
+        // Run by another thread, like an inline interpreter can be.
+        let running = thread::spawn(move || {
+            interpreter.scope(|py| {
+                let err = py.run("while True: pass", None, None).unwrap_err();
+                err.is_instance_of::<PyKeyboardInterrupt>(py)
+            })
+        });
+
+        interrupt_until_running(&interrupt);
+        assert!(running.join().unwrap());
+    }
+
+    #[test]
+    fn watchdog_interrupts_until_the_code_finishes() {
+        let interpreter =
+            Python::with_gil(|py| Interpreter::create(py, InterpreterConfig::isolated())).unwrap();
+        let interrupt = Arc::downgrade(interpreter.interrupt());
+
+        let (result, timed_out) = interpreter.scope(|py| {
+            let watchdog = Watchdog::start(interrupt, Duration::from_millis(20)).unwrap();
+            // An exception raised by a loop's own jump isn't caught by a `try` around the
+            // loop, so the loop runs within a function instead.
+            let code = [
+                "def spin():",
+                "    while True: pass",
+                "interrupts = 0",
+                "while interrupts < 2:",
+                "    try:",
+                "        spin()",
+                "    except Exception:",
+                "        raise AssertionError('caught by `except Exception`')",
+                "    except BaseException:",
+                "        interrupts += 1",
+            ]
+            .join("\n");
+            let result = py.run(&code, None, None);
+            (result.map_err(|err| err.to_string()), watchdog.finish())
+        });
+
+        assert_eq!(result, Ok(()));
+        assert!(timed_out);
+    }
+}
@@ -0,0 +1,92 @@
+use std::sync::{Arc, Mutex};
+
+use pyo3::{pyclass, PyResult};
+
+use crate::subinterpreter::shutdown_err;
+
+#[pyclass]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+/// Where a `SubInterpreter` is within its lifecycle.
+pub enum InterpreterState {
+    /// The interpreter has been created but has not run anything yet.
+    #[default]
+    Created,
+    /// The interpreter is currently running code.
+    Running,
+    /// The interpreter has run code before and is waiting for more.
+    Idle,
+    /// The interpreter is being shutdown, no new code can be run.
+    ShuttingDown,
+    /// The interpreter has been shutdown.
+    Closed,
+}
+
+impl InterpreterState {
+    /// Returns `true` if the interpreter has not started shutting down.
+    pub(crate) fn is_alive(self) -> bool {
+        !matches!(self, Self::ShuttingDown | Self::Closed)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+/// The shared lifecycle state of a `SubInterpreter`.
+///
+/// This is shared with any jobs sent to the interpreter's dedicated thread, so they
+/// can mark the interpreter as running while they are.
+pub(crate) struct Lifecycle(Arc<Mutex<InterpreterState>>);
+
+impl Lifecycle {
+    pub(crate) fn state(&self) -> InterpreterState {
+        *self.0.lock().unwrap()
+    }
+
+    /// Returns an error if the interpreter has started shutting down.
+    pub(crate) fn check_alive(&self) -> PyResult<()> {
+        if self.state().is_alive() {
+            Ok(())
+        } else {
+            Err(shutdown_err())
+        }
+    }
+
+    /// Marks the interpreter as running while calling `f`.
+    ///
+    /// Only one thread can be running code within the interpreter at any one time,
+    /// which the caller is expected to have already made sure of.
+    pub(crate) fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.transition(InterpreterState::Running);
+        let result = f();
+        self.transition(InterpreterState::Idle);
+        result
+    }
+
+    /// Marks the interpreter as shutting down.
+    ///
+    /// Returns `false` if the interpreter has already been shutdown, in which case
+    /// the caller must not shut it down again.
+    pub(crate) fn begin_shutdown(&self) -> bool {
+        let mut state = self.0.lock().unwrap();
+        if *state == InterpreterState::Closed {
+            return false;
+        }
+
+        *state = InterpreterState::ShuttingDown;
+        true
+    }
+
+    /// Marks the interpreter as shutdown.
+    pub(crate) fn close(&self) {
+        *self.0.lock().unwrap() = InterpreterState::Closed;
+    }
+
+    /// Moves to the new state, unless the interpreter has started shutting down.
+    ///
+    /// Work which was already queued still runs during shutdown, so that must not
+    /// mark the interpreter as alive again.
+    fn transition(&self, new: InterpreterState) {
+        let mut state = self.0.lock().unwrap();
+        if state.is_alive() {
+            *state = new;
+        }
+    }
+}
@@ -0,0 +1,32 @@
+import io
+
+
+class OutputWriter(io.TextIOBase):
+    """
+    A text stream which passes everything written to it on to `write`.
+
+    This is installed as `sys.stdout` and `sys.stderr` within sub-interpreters
+    whose output has been redirected.
+    """
+
+    def __init__(self, name, write):
+        self._name = name
+        self._write = write
+
+    @property
+    def name(self):
+        return f"<{self._name}>"
+
+    @property
+    def encoding(self):
+        return "utf-8"
+
+    def writable(self):
+        return True
+
+    def write(self, text):
+        if not isinstance(text, str):
+            raise TypeError(f"write() argument must be str, not {type(text).__name__}")
+
+        self._write(text)
+        return len(text)
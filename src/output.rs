@@ -0,0 +1,186 @@
+use std::sync::{Arc, Mutex};
+
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::types::{PyCFunction, PyDict, PyString, PyTuple};
+use pyo3::{pyclass, pymethods, PyAny, PyResult, Python};
+
+use crate::dispatch::{self, MainObject};
+use crate::subinterpreter::import_embedded;
+
+/// The name the output module is imported as within sub-interpreters.
+const MODULE_NAME: &str = "_subinterpreters_output";
+
+#[pyclass(frozen)]
+#[derive(Debug, Default, Clone)]
+/// Text written to `sys.stdout` and `sys.stderr` within a sub-interpreter.
+pub struct CapturedOutput {
+    #[pyo3(get)]
+    stdout: String,
+    #[pyo3(get)]
+    stderr: String,
+}
+
+#[pymethods]
+impl CapturedOutput {
+    fn __repr__(&self) -> String {
+        format!(
+            "CapturedOutput(stdout={:?}, stderr={:?})",
+            self.stdout, self.stderr
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Stdout => "stdout",
+            Self::Stderr => "stderr",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// Output buffered in memory until it is read.
+pub(crate) struct OutputBuffer(Mutex<CapturedOutput>);
+
+impl OutputBuffer {
+    fn write(&self, stream: Stream, text: &str) {
+        let mut output = self.0.lock().unwrap();
+        match stream {
+            Stream::Stdout => output.stdout.push_str(text),
+            Stream::Stderr => output.stderr.push_str(text),
+        }
+    }
+
+    /// Takes everything written so far, leaving the buffer empty.
+    pub(crate) fn take(&self) -> CapturedOutput {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+#[derive(Clone)]
+/// Where a sub-interpreter's `sys.stdout` and `sys.stderr` are sent.
+pub(crate) enum OutputTarget {
+    /// The interpreter's original streams, which write to the process' stdout and stderr.
+    Inherit,
+    /// An in-memory buffer.
+    Capture(Arc<OutputBuffer>),
+    /// A function within the main interpreter, which is called with the stream name and text.
+    Callback(Arc<MainObject>),
+}
+
+impl OutputTarget {
+    /// Gets the target from either `None`, `"capture"` or a callable.
+    pub(crate) fn from_py(target: Option<&PyAny>) -> PyResult<Self> {
+        let Some(target) = target else {
+            return Ok(Self::Inherit);
+        };
+
+        if let Ok(name) = target.downcast::<PyString>() {
+            return match name.to_str()? {
+                "capture" => Ok(Self::Capture(Arc::default())),
+                other => Err(PyValueError::new_err(format!(
+                    "output target must be None, \"capture\" or a callable, not {other:?}."
+                ))),
+            };
+        }
+
+        if !target.is_callable() {
+            return Err(PyTypeError::new_err(
+                "output target must be None, \"capture\" or a callable.",
+            ));
+        }
+
+        Ok(Self::Callback(Arc::new(MainObject::new(target.into()))))
+    }
+
+    /// The buffer output is written to, if the target is buffering output.
+    pub(crate) fn buffer(&self) -> Option<Arc<OutputBuffer>> {
+        match self {
+            Self::Capture(buffer) => Some(buffer.clone()),
+            _ => None,
+        }
+    }
+
+    /// Replaces `sys.stdout` and `sys.stderr` within the current interpreter.
+    pub(crate) fn install(&self, py: Python) -> PyResult<()> {
+        let sys = py.import("sys")?;
+
+        for stream in [Stream::Stdout, Stream::Stderr] {
+            let writer = match self {
+                Self::Inherit => sys.getattr(format!("__{}__", stream.name()).as_str())?,
+                _ => self.writer(py, stream)?,
+            };
+            sys.setattr(stream.name(), writer)?;
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, stream: Stream, text: String) {
+        match self {
+            Self::Inherit => {}
+            Self::Capture(buffer) => buffer.write(stream, &text),
+            Self::Callback(callback) => {
+                let callback = callback.clone();
+                dispatch::call_soon(move |py| {
+                    if let Err(e) = callback.get(py).call1((stream.name(), text)) {
+                        e.print(py);
+                    }
+                });
+            }
+        }
+    }
+
+    /// Creates a text stream within the current interpreter which writes to the target.
+    fn writer<'py>(&self, py: Python<'py>, stream: Stream) -> PyResult<&'py PyAny> {
+        let target = self.clone();
+        let write = PyCFunction::new_closure(
+            py,
+            Some("write\0"),
+            None,
+            move |args: &PyTuple, _kwargs: Option<&PyDict>| -> PyResult<()> {
+                target.write(stream, args.get_item(0)?.extract()?);
+                Ok(())
+            },
+        )?;
+
+        writer_class(py)?.call1((stream.name(), write))
+    }
+}
+
+/// Runs `f` with the current interpreter's output written to the buffer instead,
+/// restoring the original streams afterwards.
+pub(crate) fn capture<T>(
+    py: Python,
+    buffer: Arc<OutputBuffer>,
+    f: impl FnOnce() -> PyResult<T>,
+) -> PyResult<T> {
+    let sys = py.import("sys")?;
+    let stdout = sys.getattr("stdout")?;
+    let stderr = sys.getattr("stderr")?;
+
+    OutputTarget::Capture(buffer).install(py)?;
+    let result = f();
+
+    sys.setattr("stdout", stdout)?;
+    sys.setattr("stderr", stderr)?;
+    result
+}
+
+/// Gets the `OutputWriter` class for the current interpreter, importing it the first time.
+fn writer_class(py: Python<'_>) -> PyResult<&PyAny> {
+    import_embedded(
+        py,
+        MODULE_NAME,
+        "subinterpreters/output.py",
+        include_str!("output.py"),
+    )?
+    .getattr("OutputWriter")
+}
@@ -0,0 +1,304 @@
+// The `#[new]` trampoline generated by pyo3 trips a lint which newer compilers added.
+#![allow(non_local_definitions)]
+
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::types::{PyDict, PyTuple};
+use pyo3::{pyclass, pyfunction, pymethods, PyAny, PyObject, PyResult, Python};
+
+use crate::compile::SourceCode;
+use crate::config::ConfigOptions;
+use crate::exceptions::InterpreterShutdownError;
+use crate::future::PendingFuture;
+use crate::registry::{self, Shutdown};
+use crate::shareable::{ShareError, SharedValue};
+use crate::transport::Transport;
+use crate::worker::{call_function, run_script, Job, Worker};
+use crate::{CreateInterpreterError, InterpreterConfig};
+
+#[pyfunction]
+#[pyo3(signature = (size, allow_fork = None, allow_exec = None, allow_threads = None, allow_daemon_threads = None, use_main_obmalloc = None, check_multi_interp_extensions = None, gil = None, config = None, transport = "shared", pickle_protocol = None))]
+/// Creates a pool of `size` Python interpreters, each with it's own isolated GIL
+/// and running on it's own OS thread.
+///
+/// This method takes the same optional config arguments as `create_interpreter`
+/// (including `config`), which are used for every interpreter in the pool, along with
+/// the same `transport` and `pickle_protocol` arguments.
+///
+/// This is the same as calling `InterpreterPool(size, ...)` directly.
+#[allow(clippy::too_many_arguments)]
+pub fn create_pool(
+    py: Python,
+    size: usize,
+    allow_fork: Option<bool>,
+    allow_exec: Option<bool>,
+    allow_threads: Option<bool>,
+    allow_daemon_threads: Option<bool>,
+    use_main_obmalloc: Option<bool>,
+    check_multi_interp_extensions: Option<bool>,
+    gil: Option<&str>,
+    config: Option<InterpreterConfig>,
+    transport: &str,
+    pickle_protocol: Option<i32>,
+) -> PyResult<InterpreterPool> {
+    InterpreterPool::new(
+        py,
+        size,
+        config,
+        allow_fork,
+        allow_exec,
+        allow_threads,
+        allow_daemon_threads,
+        use_main_obmalloc,
+        check_multi_interp_extensions,
+        gil,
+        transport,
+        pickle_protocol,
+    )
+}
+
+#[pyclass]
+/// A fixed size pool of sub-interpreters which run submitted work in parallel.
+///
+/// Work is picked up by whichever interpreter is free first, so no state should be
+/// expected to carry over between submissions.
+pub struct InterpreterPool {
+    size: usize,
+    workers: Arc<PoolWorkers>,
+    /// How values are copied into and out of the pool's interpreters.
+    transport: Transport,
+}
+
+/// The worker threads behind an `InterpreterPool`, which are shared with the registry
+/// of live interpreters so they can be shutdown when the main interpreter exits.
+struct PoolWorkers {
+    jobs: Mutex<Option<Sender<Job>>>,
+    workers: Mutex<Vec<Worker>>,
+}
+
+#[pymethods]
+impl InterpreterPool {
+    #[new]
+    #[pyo3(signature = (size, config = None, *, allow_fork = None, allow_exec = None, allow_threads = None, allow_daemon_threads = None, use_main_obmalloc = None, check_multi_interp_extensions = None, gil = None, transport = "shared", pickle_protocol = None))]
+    /// Creates a pool of `size` interpreters, see `create_pool` for the other arguments,
+    /// which can only be given by keyword here.
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        py: Python,
+        size: usize,
+        config: Option<InterpreterConfig>,
+        allow_fork: Option<bool>,
+        allow_exec: Option<bool>,
+        allow_threads: Option<bool>,
+        allow_daemon_threads: Option<bool>,
+        use_main_obmalloc: Option<bool>,
+        check_multi_interp_extensions: Option<bool>,
+        gil: Option<&str>,
+        transport: &str,
+        pickle_protocol: Option<i32>,
+    ) -> PyResult<Self> {
+        if size == 0 {
+            return Err(PyValueError::new_err("pool size must be at least 1."));
+        }
+        let transport = Transport::from_py(py, transport, pickle_protocol)?;
+
+        let options = ConfigOptions {
+            allow_fork,
+            allow_exec,
+            allow_threads,
+            allow_daemon_threads,
+            use_main_obmalloc,
+            check_multi_interp_extensions,
+            gil,
+        };
+        let config = options
+            .apply(config.unwrap_or_else(InterpreterConfig::isolated))
+            .map_err(CreateInterpreterError::from)?;
+
+        let (tx, rx) = mpsc::channel();
+        let rx = Arc::new(Mutex::new(rx));
+
+        let workers = (0..size)
+            .map(|_| Worker::spawn(py, config, rx.clone()))
+            .collect::<PyResult<Vec<_>>>()?;
+
+        let workers = Arc::new(PoolWorkers {
+            jobs: Mutex::new(Some(tx)),
+            workers: Mutex::new(workers),
+        });
+        registry::register(Arc::downgrade(&workers) as _);
+        for worker in workers.workers.lock().unwrap().iter() {
+            registry::track(worker.info(), None);
+        }
+
+        Ok(Self {
+            size,
+            workers,
+            transport,
+        })
+    }
+
+    #[getter]
+    /// The number of interpreters in the pool.
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Run a Python script within one of the pool's interpreters.
+    ///
+    /// Returns a `concurrent.futures.Future` which resolves to `None` once the script
+    /// has finished.
+    fn submit(&self, py: Python, code: String) -> PyResult<PyObject> {
+        let (pending, future) = PendingFuture::concurrent(py)?;
+        self.spawn(pending, run_script(code))?;
+        Ok(future)
+    }
+
+    /// Run a Python script within one of the pool's interpreters.
+    ///
+    /// Returns an awaitable which resolves to `None` once the script has finished,
+    /// this must be called from within a running `asyncio` event loop.
+    fn submit_async(&self, py: Python, code: String) -> PyResult<PyObject> {
+        let (pending, future) = PendingFuture::asyncio(py)?;
+        self.spawn(pending, run_script(code))?;
+        Ok(future)
+    }
+
+    /// Call a function with each of the given items within the pool's interpreters.
+    ///
+    /// `func_source` is a Python expression which evaluates to the function to call,
+    /// for example `"lambda x: x * 2"`. Each item is copied into the interpreter and so
+    /// must be shareable (or picklable, with the `"pickle"` transport), as must the value
+    /// returned by the function.
+    ///
+    /// Returns a list of `concurrent.futures.Future`s, one for each item.
+    fn map(&self, py: Python, func_source: String, items: &PyAny) -> PyResult<Vec<PyObject>> {
+        let func_source = SourceCode::new(&func_source, "<string>", true);
+        let transport = self.transport;
+
+        items
+            .iter()?
+            .map(|item| {
+                let item = transport.dump(item?)?;
+                let func_source = func_source.clone();
+
+                let (pending, future) = PendingFuture::concurrent(py)?;
+                self.spawn(pending, move |py| {
+                    let func = func_source.eval(py)?;
+                    let result = func.call1((transport.load(py, item)?,))?;
+                    transport.dump_remote(result)
+                })?;
+
+                Ok(future)
+            })
+            .collect()
+    }
+
+    #[pyo3(signature = (func, *args, **kwargs))]
+    /// Call a function within one of the pool's interpreters.
+    ///
+    /// `func` is either a module level function or its qualified name (e.g. `"math.sqrt"`),
+    /// which is imported again within the interpreter. The arguments are copied into the
+    /// interpreter and so must be shareable, as must the value returned by the function.
+    ///
+    /// Returns a `concurrent.futures.Future` for the result.
+    fn call(
+        &self,
+        py: Python,
+        func: &PyAny,
+        args: &PyTuple,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<PyObject> {
+        let (pending, future) = PendingFuture::concurrent(py)?;
+        let call = call_function(func, args, kwargs, self.transport)?;
+        self.spawn(pending, call)?;
+        Ok(future)
+    }
+
+    #[pyo3(signature = (func, *args, **kwargs))]
+    /// The same as `call`, but returns an awaitable for the result instead.
+    ///
+    /// This must be called from within a running `asyncio` event loop.
+    fn call_async(
+        &self,
+        py: Python,
+        func: &PyAny,
+        args: &PyTuple,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<PyObject> {
+        let (pending, future) = PendingFuture::asyncio(py)?;
+        let call = call_function(func, args, kwargs, self.transport)?;
+        self.spawn(pending, call)?;
+        Ok(future)
+    }
+
+    #[pyo3(signature = (wait = true))]
+    /// Shuts down the pool.
+    ///
+    /// Any work which has already been submitted will still be completed, if `wait`
+    /// is `true` this blocks until that has happened and every interpreter is shutdown.
+    fn shutdown(&self, py: Python, wait: bool) -> PyResult<()> {
+        self.workers.begin_shutdown();
+        if wait {
+            self.workers.finish_shutdown(py, None)?;
+        }
+        Ok(())
+    }
+}
+
+impl InterpreterPool {
+    /// Queues the function to run within one of the pool's interpreters,
+    /// resolving the future with its result once complete.
+    fn spawn<F>(&self, pending: PendingFuture, f: F) -> PyResult<()>
+    where
+        F: FnOnce(Python) -> PyResult<Result<SharedValue, ShareError>> + Send + 'static,
+    {
+        let jobs = self.workers.jobs.lock().unwrap();
+        let sender = jobs
+            .as_ref()
+            .ok_or_else(|| InterpreterShutdownError::new_err("Pool has shutdown."))?;
+
+        sender
+            .send(Job::resolving(pending, self.transport, f))
+            .map_err(|_| InterpreterShutdownError::new_err("Pool has shutdown."))
+    }
+}
+
+impl Shutdown for PoolWorkers {
+    fn begin_shutdown(&self) {
+        self.jobs.lock().unwrap().take();
+        for worker in self.workers.lock().unwrap().iter() {
+            worker.info().lifecycle.begin_shutdown();
+        }
+    }
+
+    fn finish_shutdown(&self, py: Python, deadline: Option<Instant>) -> PyResult<bool> {
+        self.begin_shutdown();
+
+        let workers = std::mem::take(&mut *self.workers.lock().unwrap());
+        let running = py.allow_threads(move || {
+            workers
+                .into_iter()
+                .filter_map(|worker| worker.join_until(deadline).err())
+                .collect::<Vec<_>>()
+        });
+
+        let complete = running.is_empty();
+        self.workers.lock().unwrap().extend(running);
+        Ok(complete)
+    }
+}
+
+impl Drop for PoolWorkers {
+    fn drop(&mut self) {
+        // The workers may still be finishing their last jobs, so they are
+        // left for `shutdown_all` to wait for.
+        self.jobs.get_mut().unwrap().take();
+        for worker in self.workers.get_mut().unwrap().drain(..) {
+            registry::adopt(worker);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use pyo3::exceptions::PyRuntimeWarning;
+use pyo3::types::PyDict;
+use pyo3::{pyfunction, IntoPy, PyErr, PyResult, Python};
+
+use crate::channel::to_duration;
+use crate::dispatch;
+use crate::interrupt::{Interrupt, Signal};
+use crate::lifecycle::{InterpreterState, Lifecycle};
+use crate::subinterpreter::InterpreterHandle;
+use crate::worker::Worker;
+use crate::{Interpreter, InterpreterConfig};
+
+#[derive(Debug)]
+/// Details about an interpreter created by this module, as reported by `list_interpreters`.
+pub(crate) struct InterpreterInfo {
+    pub(crate) id: i64,
+    pub(crate) config: InterpreterConfig,
+    pub(crate) created_at: SystemTime,
+    /// The Python identifier of the interpreter's dedicated thread, if it has one.
+    pub(crate) thread: Option<u64>,
+    pub(crate) lifecycle: Lifecycle,
+    pub(crate) interrupt: Arc<Interrupt>,
+}
+
+impl InterpreterInfo {
+    pub(crate) fn new(
+        interpreter: &Interpreter,
+        config: InterpreterConfig,
+        thread: Option<u64>,
+    ) -> Self {
+        Self {
+            id: interpreter.id(),
+            config,
+            created_at: SystemTime::now(),
+            thread,
+            lifecycle: Lifecycle::default(),
+            interrupt: interpreter.interrupt().clone(),
+        }
+    }
+
+    pub(crate) fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<&'py PyDict> {
+        let created_at = self
+            .created_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+
+        let dict = PyDict::new(py);
+        dict.set_item("id", self.id)?;
+        dict.set_item("config", self.config.to_dict(py)?)?;
+        dict.set_item("state", self.lifecycle.state().into_py(py))?;
+        dict.set_item("created_at", created_at)?;
+        dict.set_item("thread", self.thread)?;
+        Ok(dict)
+    }
+}
+
+/// An interpreter created by this module, along with its `SubInterpreter`
+/// handle if it has one (interpreters within pools do not).
+struct Entry {
+    info: Weak<InterpreterInfo>,
+    handle: Option<Weak<InterpreterHandle>>,
+}
+
+/// Every interpreter which has been created and not yet dropped, in the order they were created.
+static INTERPRETERS: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+
+/// Anything which owns sub-interpreters that must be shutdown before the main interpreter.
+pub(crate) trait Shutdown: Send + Sync {
+    /// Stops any new work from being accepted, without waiting for anything.
+    fn begin_shutdown(&self);
+
+    /// Shuts down the interpreters, waiting for any running work to complete.
+    ///
+    /// Returns `false` if the deadline passed before everything was shutdown.
+    fn finish_shutdown(&self, py: Python, deadline: Option<Instant>) -> PyResult<bool>;
+}
+
+/// How long interpreters are given to finish their work when the main interpreter exits
+/// before they are interrupted.
+const EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Every owner of sub-interpreters which is still alive.
+static OWNERS: Mutex<Vec<Weak<dyn Shutdown>>> = Mutex::new(Vec::new());
+
+/// Workers whose owner was dropped without being shutdown.
+///
+/// Their interpreters are shutdown by the workers themselves once their job queue
+/// is closed, but the threads still have to be joined before the main interpreter exits.
+static ORPHANS: Mutex<Vec<Worker>> = Mutex::new(Vec::new());
+
+/// Adds the owner to the registry, so it is shutdown by `shutdown_all`.
+pub(crate) fn register(owner: Weak<dyn Shutdown>) {
+    let mut owners = OWNERS.lock().unwrap();
+    owners.retain(|owner| owner.strong_count() > 0);
+    owners.push(owner);
+}
+
+/// Adds the interpreter to the registry, so it is included by `list_interpreters`.
+pub(crate) fn track(info: &Arc<InterpreterInfo>, handle: Option<&Arc<InterpreterHandle>>) {
+    let mut interpreters = INTERPRETERS.lock().unwrap();
+    interpreters.retain(|entry| entry.info.strong_count() > 0);
+    interpreters.push(Entry {
+        info: Arc::downgrade(info),
+        handle: handle.map(Arc::downgrade),
+    });
+}
+
+/// Gets every interpreter which has not been shutdown yet.
+pub(crate) fn interpreters() -> Vec<(Arc<InterpreterInfo>, Option<Arc<InterpreterHandle>>)> {
+    INTERPRETERS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|entry| {
+            let info = entry.info.upgrade()?;
+            if info.lifecycle.state() == InterpreterState::Closed {
+                return None;
+            }
+
+            let handle = match &entry.handle {
+                Some(handle) => Some(handle.upgrade()?),
+                None => None,
+            };
+            Some((info, handle))
+        })
+        .collect()
+}
+
+/// Takes ownership of a worker which nothing else will join.
+pub(crate) fn adopt(worker: Worker) {
+    let mut orphans = ORPHANS.lock().unwrap();
+    orphans.retain(|worker| !worker.is_finished());
+    orphans.push(worker);
+}
+
+#[pyfunction]
+#[pyo3(signature = (timeout = None))]
+/// Shuts down every interpreter (and pool) created by this module.
+///
+/// Interpreters running on their own threads finish any work they have already been
+/// given first, `timeout` is the maximum number of seconds to wait for them in total.
+///
+/// Returns `True` if everything was shutdown, or `False` if some interpreters were still
+/// busy when the timeout passed.
+///
+/// This is called automatically when the main interpreter exits, in which case any
+/// interpreters still busy after a few seconds are interrupted with a `KeyboardInterrupt`.
+pub fn shutdown_all(py: Python, timeout: Option<f64>) -> PyResult<bool> {
+    let deadline = timeout
+        .map(to_duration)
+        .transpose()?
+        .map(|timeout| Instant::now() + timeout);
+
+    let owners: Vec<_> = OWNERS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect();
+
+    // Every owner stops taking work first, so their threads wind down together
+    // rather than one after another.
+    for owner in owners.iter() {
+        owner.begin_shutdown();
+    }
+
+    let mut complete = true;
+    for owner in owners.iter() {
+        complete &= owner.finish_shutdown(py, deadline).unwrap_or(false);
+    }
+
+    let orphans = std::mem::take(&mut *ORPHANS.lock().unwrap());
+    let running = py.allow_threads(move || {
+        orphans
+            .into_iter()
+            .filter_map(|worker| worker.join_until(deadline).err())
+            .collect::<Vec<_>>()
+    });
+
+    complete &= running.is_empty();
+    ORPHANS.lock().unwrap().extend(running);
+
+    // Shutting down interpreters can release objects owned by the main interpreter,
+    // which must happen before it exits.
+    py.allow_threads(dispatch::flush);
+
+    Ok(complete)
+}
+
+#[pyfunction]
+/// Shuts down every interpreter before the main interpreter exits.
+///
+/// Interpreters which are still busy after `EXIT_TIMEOUT` (e.g. stuck in a `while True:`
+/// loop or a blocking `recv`) are interrupted, and then interrupted again each time the
+/// timeout passes. They can't be abandoned instead, since Python aborts the process if
+/// any sub-interpreters are still alive when the main interpreter is finalized.
+pub(crate) fn shutdown_at_exit(py: Python) -> PyResult<()> {
+    let timeout = Some(EXIT_TIMEOUT.as_secs_f64());
+    let mut warned = false;
+
+    while !shutdown_all(py, timeout)? {
+        if !warned {
+            PyErr::warn(
+                py,
+                py.get_type::<PyRuntimeWarning>(),
+                "interrupting sub-interpreters which are still running at exit.",
+                0,
+            )?;
+            warned = true;
+        }
+
+        let busy = interpreters();
+        py.allow_threads(|| {
+            for (info, _) in busy.iter() {
+                info.interrupt.interrupt(Signal::Interrupt);
+            }
+        });
+    }
+
+    Ok(())
+}
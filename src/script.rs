@@ -0,0 +1,109 @@
+use std::path::Path;
+
+use pyo3::types::{PyDict, PyList};
+use pyo3::{IntoPy, PyAny, PyObject, PyResult, Python};
+
+use crate::subinterpreter::main_namespace;
+
+/// Runs the Python file within the `__main__` namespace, like `python path *argv` would.
+///
+/// `__file__` is set and `sys.path[0]` is set to the file's directory while it runs,
+/// and both are restored afterwards.
+pub(crate) fn run_file(py: Python, path: &Path, argv: Vec<String>) -> PyResult<()> {
+    with_argv(py, path.into_py(py), argv, || {
+        // `tokenize.open` detects the file's encoding the same way Python itself does.
+        let file = py.import("tokenize")?.call_method1("open", (path,))?;
+        let source = file.call_method0("read");
+        file.call_method0("close")?;
+
+        let builtins = py.import("builtins")?;
+        let code = builtins
+            .getattr("compile")?
+            .call1((source?, path, "exec"))?;
+
+        // Python itself uses the directory of the script with any symlinks resolved.
+        let os_path = py.import("os.path")?;
+        let directory =
+            os_path.call_method1("dirname", (os_path.call_method1("realpath", (path,))?,))?;
+
+        let namespace = main_namespace(py)?;
+        let previous_file = namespace.get_item("__file__");
+        namespace.set_item("__file__", path)?;
+
+        let result = with_sys_path0(py, directory, || {
+            builtins.getattr("exec")?.call1((code, namespace))?;
+            Ok(())
+        });
+
+        match previous_file {
+            Some(previous) => namespace.set_item("__file__", previous)?,
+            None if namespace.contains("__file__")? => namespace.del_item("__file__")?,
+            None => {}
+        }
+        result
+    })
+}
+
+/// Runs the module as `__main__`, like `python -m name *argv` would.
+pub(crate) fn run_module(py: Python, name: &str, argv: Vec<String>) -> PyResult<()> {
+    with_argv(py, name.into_py(py), argv, || {
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("run_name", "__main__")?;
+        // Sets `sys.argv[0]` to the module's file and gives it its own `__main__` module.
+        kwargs.set_item("alter_sys", true)?;
+
+        py.import("runpy")?
+            .call_method("run_module", (name,), Some(kwargs))?;
+        Ok(())
+    })
+}
+
+/// Runs `f` with `sys.argv` set to `argv0` followed by `argv`, restoring it afterwards.
+fn with_argv<T>(
+    py: Python,
+    argv0: PyObject,
+    argv: Vec<String>,
+    f: impl FnOnce() -> PyResult<T>,
+) -> PyResult<T> {
+    let sys = py.import("sys")?;
+    let original = sys.getattr("argv")?;
+
+    let args = PyList::new(py, [argv0]);
+    for arg in argv {
+        args.append(arg)?;
+    }
+    sys.setattr("argv", args)?;
+
+    let result = f();
+    sys.setattr("argv", original)?;
+    result
+}
+
+/// Runs `f` with `sys.path[0]` set to `directory`, restoring it afterwards unless
+/// `f` changed it itself.
+fn with_sys_path0<T>(
+    py: Python,
+    directory: &PyAny,
+    f: impl FnOnce() -> PyResult<T>,
+) -> PyResult<T> {
+    let path = py.import("sys")?.getattr("path")?.downcast::<PyList>()?;
+    let original = path.get_item(0).ok();
+    match original {
+        Some(_) => path.set_item(0, directory)?,
+        None => path.insert(0, directory)?,
+    }
+
+    let result = f();
+
+    let unchanged = match path.get_item(0) {
+        Ok(first) => first.eq(directory)?,
+        Err(_) => false,
+    };
+    if unchanged {
+        match original {
+            Some(original) => path.set_item(0, original)?,
+            None => path.del_item(0)?,
+        }
+    }
+    result
+}
@@ -0,0 +1,449 @@
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::types::{
+    IntoPyDict, PyBool, PyBytes, PyDict, PyFloat, PyList, PyLong, PyString, PyTuple,
+};
+use pyo3::{FromPyObject, IntoPy, PyAny, PyErr, PyObject, PyResult, Python, ToPyObject};
+#[cfg(feature = "python-module")]
+use pyo3::{Py, PyCell};
+#[cfg(feature = "python-module")]
+use std::sync::Arc;
+
+#[cfg(feature = "python-module")]
+use crate::channel::{self, Channel, ChannelState};
+use crate::exceptions::RemoteError;
+#[cfg(feature = "python-module")]
+use crate::interpreter::is_main_interpreter;
+#[cfg(feature = "python-module")]
+use crate::transport::Transport;
+
+/// The maximum depth containers can be nested before we refuse to share them.
+///
+/// This mostly exists to stop self-referencing containers from overflowing the stack.
+const MAX_DEPTH: usize = 64;
+
+/// Values which contain no Python objects, so can safely leave the interpreter they
+/// were created within, see `Interpreter::with_gil`.
+///
+/// # Safety
+///
+/// Types must not contain any Python objects (`Py<T>`, `PyErr`, etc...) or anything
+/// else which is tied to a particular interpreter, nor be able to create them.
+pub unsafe trait Shareable: Send + 'static {}
+
+macro_rules! impl_shareable {
+    ($($ty:ty),* $(,)?) => {
+        $(unsafe impl Shareable for $ty {})*
+    };
+}
+
+impl_shareable!(
+    (),
+    bool,
+    char,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    f32,
+    f64,
+    String,
+    SharedValue,
+    SharedNamespace,
+    ShareError,
+    RemoteError,
+);
+
+unsafe impl<T: Shareable> Shareable for Option<T> {}
+unsafe impl<T: Shareable> Shareable for Box<T> {}
+unsafe impl<T: Shareable> Shareable for Vec<T> {}
+unsafe impl<T: Shareable, E: Shareable> Shareable for Result<T, E> {}
+unsafe impl<A: Shareable, B: Shareable> Shareable for (A, B) {}
+unsafe impl<A: Shareable, B: Shareable, C: Shareable> Shareable for (A, B, C) {}
+
+#[derive(Debug, Clone)]
+/// A plain Rust copy of a Python value which can be safely moved between interpreters.
+///
+/// Python objects belong to the interpreter (and allocator) that created them, so
+/// rather than passing them around directly, values are extracted into a `SharedValue`
+/// within the source interpreter and rebuilt as new objects within the destination one.
+///
+/// This is how every value crossing between interpreters is copied, whether that's
+/// namespaces given to `run_code`, results from `eval`, messages sent over channels or
+/// the arguments of exceptions. Lists and dicts are deep copied, so changes made to the
+/// copy are never seen by the original.
+///
+/// Channels only exist within the Python module, so the `Channel` variant is only
+/// available with the `python-module` feature.
+#[non_exhaustive]
+pub enum SharedValue {
+    None,
+    Bool(bool),
+    Int(i64),
+    /// An `int` too large for an `i64`, as little-endian two's complement bytes.
+    BigInt(Vec<u8>),
+    Float(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+    Tuple(Vec<SharedValue>),
+    List(Vec<SharedValue>),
+    Dict(Vec<(SharedValue, SharedValue)>),
+    /// A handle to a `Channel`, every copy refers to the same queue.
+    #[cfg(feature = "python-module")]
+    Channel(Arc<ChannelState>),
+    /// An object pickled by the `"pickle"` transport, which only that transport unpickles
+    /// again. It is rebuilt as the pickled `bytes` otherwise.
+    #[cfg(feature = "python-module")]
+    Pickled(Vec<u8>),
+}
+
+impl SharedValue {
+    /// Copies the given Python object into a new `SharedValue`.
+    ///
+    /// This must be called while the interpreter which owns `obj` is active.
+    pub fn extract_from(py: Python, obj: &PyAny) -> Result<Self, ShareError> {
+        Self::extract_nested(py, obj, 0)
+    }
+
+    fn extract_nested(py: Python, obj: &PyAny, depth: usize) -> Result<Self, ShareError> {
+        if depth > MAX_DEPTH {
+            return Err(ShareError::TooDeep);
+        }
+
+        if obj.is_none() {
+            return Ok(Self::None);
+        }
+
+        // `bool` is a subclass of `int` so it must be checked first.
+        if let Ok(value) = obj.downcast::<PyBool>() {
+            return Ok(Self::Bool(value.is_true()));
+        }
+
+        if let Ok(value) = obj.downcast::<PyLong>() {
+            if let Ok(value) = value.extract::<i64>() {
+                return Ok(Self::Int(value));
+            }
+            return Self::extract_big_int(py, value).map_err(|_| ShareError::InvalidInt);
+        }
+
+        if let Ok(value) = obj.downcast::<PyFloat>() {
+            return Ok(Self::Float(value.value()));
+        }
+
+        if let Ok(value) = obj.downcast::<PyString>() {
+            let value = value.to_str().map_err(|_| ShareError::InvalidString)?;
+            return Ok(Self::Str(value.to_string()));
+        }
+
+        if let Ok(value) = obj.downcast::<PyBytes>() {
+            return Ok(Self::Bytes(value.as_bytes().to_vec()));
+        }
+
+        if let Ok(value) = obj.downcast::<PyTuple>() {
+            let items = value
+                .iter()
+                .map(|item| Self::extract_nested(py, item, depth + 1))
+                .collect::<Result<_, _>>()?;
+            return Ok(Self::Tuple(items));
+        }
+
+        if let Ok(value) = obj.downcast::<PyList>() {
+            let items = value
+                .iter()
+                .map(|item| Self::extract_nested(py, item, depth + 1))
+                .collect::<Result<_, _>>()?;
+            return Ok(Self::List(items));
+        }
+
+        if let Ok(value) = obj.downcast::<PyDict>() {
+            let items = value
+                .iter()
+                .map(|(key, value)| {
+                    Ok((
+                        Self::extract_nested(py, key, depth + 1)?,
+                        Self::extract_nested(py, value, depth + 1)?,
+                    ))
+                })
+                .collect::<Result<_, _>>()?;
+            return Ok(Self::Dict(items));
+        }
+
+        #[cfg(feature = "python-module")]
+        if let Some(state) = Self::extract_channel(obj) {
+            return Ok(Self::Channel(state));
+        }
+
+        let type_name = obj.get_type().name().unwrap_or("<unknown>");
+        Err(ShareError::Unsupported(type_name.to_string()))
+    }
+
+    #[cfg(feature = "python-module")]
+    /// Gets the channel behind the object, if it is a `Channel` (or a proxy for one).
+    pub(crate) fn extract_channel(obj: &PyAny) -> Option<Arc<ChannelState>> {
+        // The `Channel` class only exists within the main interpreter, sub-interpreters
+        // are given a proxy object instead.
+        if is_main_interpreter() {
+            let channel = obj.downcast::<PyCell<Channel>>().ok()?;
+            let state = channel.borrow().0.clone();
+            Some(state)
+        } else {
+            channel::extract_proxy(obj)
+        }
+    }
+
+    fn extract_big_int(py: Python, value: &PyLong) -> PyResult<Self> {
+        // One extra bit is needed for the sign, which `bit_length` doesn't include.
+        let bits = value.call_method0("bit_length")?.extract::<usize>()?;
+        let length = bits / 8 + 1;
+
+        let kwargs = [("signed", true)].into_py_dict(py);
+        let bytes = value.call_method("to_bytes", (length, "little"), Some(kwargs))?;
+        Ok(Self::BigInt(
+            bytes.downcast::<PyBytes>()?.as_bytes().to_vec(),
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// A copy of a namespace dict (e.g. `globals()`) containing only its shareable entries.
+pub struct SharedNamespace(Vec<(String, SharedValue)>);
+
+impl SharedNamespace {
+    /// Copies every shareable entry from the given dict.
+    ///
+    /// Entries which are not keyed by a `str` or whose values cannot be shared
+    /// (modules, functions, classes, etc...) are skipped rather than treated as an error,
+    /// since almost every real namespace contains at least a few of them.
+    pub fn copy_from(dict: &PyDict) -> Self {
+        Self::copy_filtered(dict, |value| {
+            SharedValue::extract_from(value.py(), value).ok()
+        })
+    }
+
+    #[cfg(feature = "python-module")]
+    /// Like `copy_from`, but copies each value using the given transport.
+    pub(crate) fn copy_with(dict: &PyDict, transport: Transport) -> Self {
+        Self::copy_filtered(dict, |value| transport.dump(value).ok())
+    }
+
+    /// Copies every entry keyed by a `str`, skipping values which `copy` returns `None` for.
+    fn copy_filtered(dict: &PyDict, copy: impl Fn(&PyAny) -> Option<SharedValue>) -> Self {
+        let entries = dict
+            .iter()
+            .filter_map(|(key, value)| {
+                let key = key.downcast::<PyString>().ok()?.to_str().ok()?;
+                if key == "__builtins__" {
+                    return None;
+                }
+
+                Some((key.to_string(), copy(value)?))
+            })
+            .collect();
+
+        Self(entries)
+    }
+
+    /// Creates a new dict owned by the currently active interpreter containing the entries.
+    pub fn into_dict(self, py: Python<'_>) -> &PyDict {
+        let dict = PyDict::new(py);
+        for (key, value) in self.0 {
+            // `str` keys are always hashable.
+            dict.set_item(key, value.into_py(py))
+                .expect("shared namespace keys should always be hashable");
+        }
+        dict
+    }
+
+    #[cfg(feature = "python-module")]
+    /// Like `into_dict`, but rebuilds each value using the transport it was copied with.
+    pub(crate) fn into_dict_with(self, py: Python<'_>, transport: Transport) -> PyResult<&PyDict> {
+        let dict = PyDict::new(py);
+        self.update_with(dict, transport)?;
+        Ok(dict)
+    }
+
+    /// Inserts the entries into the given dict, replacing any existing values.
+    pub fn update(self, dict: &PyDict) -> PyResult<()> {
+        let py = dict.py();
+        for (key, value) in self.0 {
+            dict.set_item(key, value.into_py(py))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "python-module")]
+    /// Like `update`, but rebuilds each value using the transport it was copied with.
+    ///
+    /// Like the values `copy_with` fails to copy, values which fail to be rebuilt are skipped,
+    /// such as a pickled class which can't be imported by the current interpreter.
+    pub(crate) fn update_with(self, dict: &PyDict, transport: Transport) -> PyResult<()> {
+        let py = dict.py();
+        for (key, value) in self.0 {
+            if let Ok(value) = transport.load(py, value) {
+                dict.set_item(key, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl IntoPy<PyObject> for SharedValue {
+    /// Rebuilds the value as a new object owned by the currently active interpreter.
+    fn into_py(self, py: Python<'_>) -> PyObject {
+        match self {
+            Self::None => py.None(),
+            Self::Bool(value) => value.into_py(py),
+            Self::Int(value) => value.into_py(py),
+            Self::BigInt(bytes) => {
+                let kwargs = [("signed", true)].into_py_dict(py);
+                py.get_type::<PyLong>()
+                    .call_method(
+                        "from_bytes",
+                        (PyBytes::new(py, &bytes), "little"),
+                        Some(kwargs),
+                    )
+                    .expect("failed to rebuild int")
+                    .into_py(py)
+            }
+            Self::Float(value) => value.into_py(py),
+            Self::Str(value) => value.into_py(py),
+            Self::Bytes(value) => PyBytes::new(py, &value).into_py(py),
+            Self::Tuple(items) => {
+                let items = items.into_iter().map(|item| item.into_py(py));
+                PyTuple::new(py, items).into_py(py)
+            }
+            Self::List(items) => {
+                let items = items.into_iter().map(|item| item.into_py(py));
+                PyList::new(py, items).into_py(py)
+            }
+            Self::Dict(items) => {
+                let dict = PyDict::new(py);
+                for (key, value) in items {
+                    // Keys were hashable when they were extracted, and every shareable
+                    // type rebuilds into an equally hashable object.
+                    dict.set_item(key.into_py(py), value.into_py(py))
+                        .expect("shared dict keys should always be hashable");
+                }
+                dict.to_object(py)
+            }
+            #[cfg(feature = "python-module")]
+            Self::Channel(state) => {
+                if is_main_interpreter() {
+                    Py::new(py, Channel(state))
+                        .expect("failed to create channel")
+                        .into_py(py)
+                } else {
+                    channel::create_proxy(py, state).expect("failed to create channel proxy")
+                }
+            }
+            #[cfg(feature = "python-module")]
+            Self::Pickled(data) => PyBytes::new(py, &data).into_py(py),
+        }
+    }
+}
+
+impl<'source> FromPyObject<'source> for SharedValue {
+    fn extract(obj: &'source PyAny) -> PyResult<Self> {
+        Ok(Self::extract_from(obj.py(), obj)?)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+/// A error which occurred while copying a value between interpreters.
+pub enum ShareError {
+    #[error("objects of type `{0}` cannot be shared between interpreters.")]
+    Unsupported(String),
+    #[error("int subclass cannot be converted to an int to be shared between interpreters.")]
+    InvalidInt,
+    #[error("str contains characters which cannot be shared between interpreters.")]
+    InvalidString,
+    #[error("object is nested too deeply to be shared between interpreters.")]
+    TooDeep,
+}
+
+impl From<ShareError> for PyErr {
+    fn from(value: ShareError) -> Self {
+        match value {
+            ShareError::Unsupported(_) => PyTypeError::new_err(value.to_string()),
+            ShareError::InvalidInt | ShareError::InvalidString | ShareError::TooDeep => {
+                PyValueError::new_err(value.to_string())
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "python-module"))]
+mod tests {
+    use std::sync::Arc;
+
+    use pyo3::types::PyBytes;
+    use pyo3::{Py, Python};
+
+    use super::{SharedNamespace, SharedValue};
+    use crate::channel::{Channel, ChannelState};
+    use crate::transport::Transport;
+    use crate::{Interpreter, InterpreterConfig};
+
+    #[test]
+    fn pickle_transport_shares_channels() {
+        let transport = Transport::Pickle { protocol: None };
+        let state = Arc::new(ChannelState::default());
+
+        Python::with_gil(|py| {
+            let channel = Py::new(py, Channel(state.clone())).unwrap();
+            let value = transport.dump(channel.as_ref(py)).unwrap();
+            assert!(matches!(&value, SharedValue::Channel(copy) if Arc::ptr_eq(copy, &state)));
+
+            // Copied back out of the sub-interpreter, which is given a proxy for the channel.
+            let interpreter = Interpreter::create(py, InterpreterConfig::isolated()).unwrap();
+            let value = interpreter.scope(|py| {
+                let proxy = transport.load(py, value).unwrap();
+                transport.dump(proxy.as_ref(py)).unwrap()
+            });
+            assert!(matches!(&value, SharedValue::Channel(copy) if Arc::ptr_eq(copy, &state)));
+
+            // Pickled `bytes` are still rebuilt as `bytes`.
+            let value = transport.dump(PyBytes::new(py, b"data")).unwrap();
+            let data = transport.load(py, value).unwrap();
+            assert_eq!(data.extract::<Vec<u8>>(py).unwrap(), b"data");
+        });
+    }
+
+    #[test]
+    fn skips_entries_which_fail_to_load() {
+        let transport = Transport::Pickle { protocol: None };
+
+        Python::with_gil(|py| {
+            let main = py.import("__main__").unwrap().dict();
+            py.run(
+                "class Local: pass\nlocal = Local()\nanswer = 42",
+                Some(main),
+                None,
+            )
+            .unwrap();
+
+            // Pickled by reference to `__main__`, which the sub-interpreter can't find.
+            let namespace = SharedNamespace::copy_with(main, transport);
+            assert!(namespace.0.iter().any(|(key, _)| key == "local"));
+
+            let interpreter = Interpreter::create(py, InterpreterConfig::isolated()).unwrap();
+            let (answer, local) = interpreter.scope(|py| {
+                let dict = namespace.into_dict_with(py, transport).unwrap();
+                let answer = dict
+                    .get_item("answer")
+                    .map(|answer| answer.extract::<i64>().unwrap());
+                (answer, dict.contains("local").unwrap())
+            });
+
+            assert_eq!(answer, Some(42));
+            assert!(!local);
+        });
+    }
+}
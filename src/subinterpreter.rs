@@ -0,0 +1,809 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::time::{Duration, Instant};
+
+use pyo3::exceptions::{PyKeyError, PyTimeoutError, PyValueError};
+use pyo3::types::{PyDict, PyModule, PyTuple};
+use pyo3::{
+    pyclass, pyfunction, pymethods, IntoPy, PyAny, PyErr, PyObject, PyRef, PyResult, Python,
+};
+
+use crate::channel::to_duration;
+use crate::compile::{CompiledCode, Source, SourceCode};
+use crate::config::ConfigOptions;
+use crate::exceptions::{
+    InterpreterBusyError, InterpreterConfigError, InterpreterShutdownError, RemoteError,
+};
+use crate::future::PendingFuture;
+use crate::interrupt::{Signal, Watchdog};
+use crate::lifecycle::InterpreterState;
+use crate::output::{self, CapturedOutput, OutputBuffer, OutputTarget};
+use crate::registry::{self, InterpreterInfo, Shutdown};
+use crate::shareable::SharedNamespace;
+use crate::transport::Transport;
+use crate::worker::{call_function, run_script, Job, Worker};
+use crate::{script, CreateInterpreterError, Interpreter, InterpreterConfig};
+
+#[pyfunction]
+#[pyo3(signature = (allow_fork = None, allow_exec = None, allow_threads = None, allow_daemon_threads = None, use_main_obmalloc = None, check_multi_interp_extensions = None, gil = None, dedicated_thread = false, config = None, transport = "shared", pickle_protocol = None))]
+/// Creates a new Python interpreter with it's own isolated GIL.
+///
+/// This method takes the following optional arguments:
+/// - `config` (InterpreterConfig) - Defaults to `InterpreterConfig.isolated()`.
+/// - `allow_fork` (bool) - Defaults to `false`.
+/// - `allow_exec` (bool) - Defaults to `false`.
+/// - `allow_threads` (bool) - Defaults to `true`.
+/// - `allow_daemon_threads` (bool) - Defaults to `false`.
+/// - `use_main_obmalloc` (bool) - Defaults to `false`.
+/// - `check_multi_interp_extensions` (bool) - Defaults to `true`.
+/// - `gil` (str) - One of `"own"`, `"shared"` or `"default"`. Defaults to `"own"`.
+/// - `dedicated_thread` (bool) - Defaults to `false`.
+/// - `transport` (str) - Either `"shared"` or `"pickle"`. Defaults to `"shared"`.
+/// - `pickle_protocol` (int) - Only used with the `"pickle"` transport, defaults to
+///   `pickle.DEFAULT_PROTOCOL`.
+///
+/// Any of the other config options which are given take priority over those in `config`,
+/// the defaults listed are those of `InterpreterConfig.isolated()`.
+///
+/// Some of these configs may cause issues, use at your own risk.
+///
+/// Extension modules which don't support multiple interpreters can only be imported
+/// by "legacy" interpreters, which use the main interpreter's GIL and memory allocator,
+/// see `InterpreterConfig.legacy()`.
+///
+/// If `dedicated_thread` is `true`, the interpreter is created on and runs all of its code
+/// on its own OS thread, and callers release their GIL while waiting for it. This is what
+/// lets separate interpreters actually run in parallel.
+///
+/// The new interpreter starts with a copy of the current `sys.path`, so it can import
+/// the same modules as the caller.
+///
+/// With the `"pickle"` transport, values passed into or returned from the interpreter are
+/// pickled by one interpreter and unpickled by the other rather than being limited to
+/// shareable types. This supports most objects, but classes are pickled by name so they
+/// must be importable by both interpreters (i.e. not defined within `__main__`).
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn create_interpreter(
+    py: Python,
+    allow_fork: Option<bool>,
+    allow_exec: Option<bool>,
+    allow_threads: Option<bool>,
+    allow_daemon_threads: Option<bool>,
+    use_main_obmalloc: Option<bool>,
+    check_multi_interp_extensions: Option<bool>,
+    gil: Option<&str>,
+    dedicated_thread: bool,
+    config: Option<InterpreterConfig>,
+    transport: &str,
+    pickle_protocol: Option<i32>,
+) -> PyResult<SubInterpreter> {
+    let transport = Transport::from_py(py, transport, pickle_protocol)?;
+
+    let options = ConfigOptions {
+        allow_fork,
+        allow_exec,
+        allow_threads,
+        allow_daemon_threads,
+        use_main_obmalloc,
+        check_multi_interp_extensions,
+        gil,
+    };
+    let config = options
+        .apply(config.unwrap_or_else(InterpreterConfig::isolated))
+        .map_err(CreateInterpreterError::from)?;
+
+    if dedicated_thread {
+        let (tx, rx) = mpsc::channel();
+        let worker = Worker::spawn(py, config, Arc::new(Mutex::new(rx)))?;
+        let info = worker.info().clone();
+
+        let backend = Backend::Threaded {
+            jobs: Mutex::new(Some(tx)),
+            worker: Mutex::new(Some(worker)),
+        };
+        return Ok(SubInterpreter::new(backend, info, transport));
+    }
+
+    let sys_path = get_sys_path(py);
+    let interpreter = Interpreter::create(py, config)?;
+    interpreter.scope(|py| set_sys_path(py, sys_path));
+
+    let info = Arc::new(InterpreterInfo::new(&interpreter, config, None));
+    let backend = Backend::Inline(Mutex::new(interpreter));
+    Ok(SubInterpreter::new(backend, info, transport))
+}
+
+#[pyfunction]
+/// Lists every live interpreter created by this module, including those within pools.
+///
+/// Each interpreter is described by a dict with the following keys:
+/// - `id` (int) - The interpreter's ID, the same as `SubInterpreter.id`.
+/// - `config` (dict) - The config the interpreter was created with.
+/// - `state` (InterpreterState) - Where the interpreter is within its lifecycle.
+/// - `created_at` (float) - When the interpreter was created, in seconds since the epoch.
+/// - `thread` (int | None) - The identifier of the interpreter's dedicated thread, as
+///   returned by `threading.get_ident()`, or `None` if it does not have one.
+/// - `pool` (bool) - Whether the interpreter belongs to a pool.
+pub(crate) fn list_interpreters(py: Python<'_>) -> PyResult<Vec<&PyDict>> {
+    registry::interpreters()
+        .into_iter()
+        .map(|(info, handle)| {
+            let dict = info.to_dict(py)?;
+            dict.set_item("pool", handle.is_none())?;
+            Ok(dict)
+        })
+        .collect()
+}
+
+#[pyfunction]
+/// Gets a handle to the live interpreter with the given ID.
+///
+/// Raises a `KeyError` if there is no such interpreter, or a `ValueError` if
+/// the interpreter belongs to a pool and so cannot be used directly.
+pub(crate) fn get_interpreter(id: i64) -> PyResult<SubInterpreter> {
+    let found = registry::interpreters()
+        .into_iter()
+        .find(|(info, _)| info.id == id);
+
+    match found {
+        Some((_, Some(handle))) => Ok(SubInterpreter(handle)),
+        Some((_, None)) => Err(PyValueError::new_err(format!(
+            "interpreter {id} belongs to a pool and cannot be used directly."
+        ))),
+        None => Err(PyKeyError::new_err(id)),
+    }
+}
+
+#[pyclass]
+pub struct SubInterpreter(Arc<InterpreterHandle>);
+
+/// The state behind a `SubInterpreter`, which is shared with the registry of live
+/// interpreters so it can be shutdown when the main interpreter exits.
+pub(crate) struct InterpreterHandle {
+    backend: Backend,
+    info: Arc<InterpreterInfo>,
+    /// The buffer the interpreter's output is being captured in, if any.
+    output: Mutex<Option<Arc<OutputBuffer>>>,
+    /// How values are copied into and out of the interpreter.
+    transport: Transport,
+}
+
+/// How a `SubInterpreter` runs the code it is given.
+enum Backend {
+    /// The interpreter is entered directly by whichever thread calls into it.
+    Inline(Mutex<Interpreter>),
+    /// The interpreter lives on a dedicated worker thread which callers hand work to.
+    Threaded {
+        jobs: Mutex<Option<Sender<Job>>>,
+        worker: Mutex<Option<Worker>>,
+    },
+}
+
+#[pymethods]
+impl SubInterpreter {
+    #[pyo3(signature = (code, globals = None, locals = None, copy_back = false, timeout = None, capture_output = false, dedent = true, filename = "<string>"))]
+    /// Run a Python script within the sub-interpreter.
+    ///
+    /// The `globals` and `locals` dicts are never handed to the sub-interpreter directly,
+    /// instead their shareable values are copied into new dicts owned by the sub-interpreter.
+    /// Values which cannot be shared (modules, functions, classes, etc...) are skipped.
+    ///
+    /// If `copy_back` is `true`, the shareable values left in the namespaces once the
+    /// script has finished are copied back into the given `globals` and `locals`.
+    ///
+    /// When no `globals` are given, the script runs within the sub-interpreter's own
+    /// `__main__` namespace, which persists between calls.
+    ///
+    /// If the script is still running after `timeout` seconds, it is interrupted and a
+    /// `TimeoutError` is raised instead. The interpreter can still be used afterwards.
+    ///
+    /// If `capture_output` is `true`, anything the script writes to `sys.stdout` or
+    /// `sys.stderr` is captured and returned as a `CapturedOutput`, rather than going
+    /// to wherever the interpreter's output normally goes. If the script raises, the
+    /// output is attached to the exception as its `output` attribute instead.
+    ///
+    /// If `dedent` is `true`, the indentation shared by every line of the script is removed
+    /// before it runs, so scripts can be written inline within indented code. Line and column
+    /// numbers within `SyntaxError`s and tracebacks still match the original script, and
+    /// the `filename` is shown in them too.
+    #[allow(clippy::too_many_arguments)]
+    fn run_code(
+        &self,
+        py: Python,
+        code: String,
+        globals: Option<&PyDict>,
+        locals: Option<&PyDict>,
+        copy_back: bool,
+        timeout: Option<f64>,
+        capture_output: bool,
+        dedent: bool,
+        filename: &str,
+    ) -> PyResult<Option<CapturedOutput>> {
+        self.run(
+            py,
+            Source::Text(SourceCode::new(&code, filename, dedent)),
+            globals,
+            locals,
+            copy_back,
+            timeout,
+            capture_output,
+        )
+    }
+
+    /// Compile a Python script within the sub-interpreter, without running it.
+    ///
+    /// Returns a `CompiledCode` handle which can be run any number of times with
+    /// `run_compiled`, without compiling the script again. Any `SyntaxError` is
+    /// raised straight away. The `filename` and `dedent` options work the same as
+    /// they do for `run_code`.
+    #[pyo3(signature = (code, filename = "<string>", dedent = true))]
+    fn compile(
+        &self,
+        py: Python,
+        code: String,
+        filename: &str,
+        dedent: bool,
+    ) -> PyResult<CompiledCode> {
+        let compiled = CompiledCode::new(SourceCode::new(&code, filename, dedent));
+
+        let source = Source::Compiled(compiled.clone());
+        self.scope(py, move |py| {
+            source.compile(py)?;
+            Ok(())
+        })?;
+
+        Ok(compiled)
+    }
+
+    #[pyo3(signature = (code, globals = None, locals = None, copy_back = false, timeout = None, capture_output = false))]
+    /// Run a script compiled by `compile` within the sub-interpreter.
+    ///
+    /// This takes the same arguments as `run_code`. The handle can be run by other
+    /// interpreters as well, which compile the script themselves the first time.
+    #[allow(clippy::too_many_arguments)]
+    fn run_compiled(
+        &self,
+        py: Python,
+        code: CompiledCode,
+        globals: Option<&PyDict>,
+        locals: Option<&PyDict>,
+        copy_back: bool,
+        timeout: Option<f64>,
+        capture_output: bool,
+    ) -> PyResult<Option<CapturedOutput>> {
+        self.run(
+            py,
+            Source::Compiled(code),
+            globals,
+            locals,
+            copy_back,
+            timeout,
+            capture_output,
+        )
+    }
+
+    /// Run a Python script within the sub-interpreter without blocking the event loop.
+    ///
+    /// Returns an awaitable which resolves to `None` once the script has finished, this
+    /// must be called from within a running `asyncio` event loop.
+    ///
+    /// The script runs within the `__main__` namespace, and since it runs in the
+    /// background the interpreter must have been created with `dedicated_thread=True`.
+    fn run_code_async(&self, py: Python, code: String) -> PyResult<PyObject> {
+        let Backend::Threaded { jobs, .. } = &self.0.backend else {
+            return Err(InterpreterConfigError::new_err(
+                "run_code_async requires an interpreter created with `dedicated_thread=True`.",
+            ));
+        };
+
+        self.0.info.lifecycle.check_alive()?;
+
+        let (pending, future) = PendingFuture::asyncio(py)?;
+        let run = run_script(code);
+        let transport = self.0.transport;
+
+        send_job(jobs, Job::resolving(pending, transport, run))?;
+        Ok(future)
+    }
+
+    #[pyo3(signature = (path, argv = None))]
+    /// Run a Python file within the sub-interpreter, like `python path *argv` would.
+    ///
+    /// The file is read using the encoding it declares and compiled with its real filename,
+    /// so tracebacks point at the file. It runs within the interpreter's `__main__` namespace,
+    /// and while it runs `__file__` is set, `sys.argv` is set to `[path, *argv]` and
+    /// `sys.path[0]` is set to the file's directory.
+    fn run_file(&self, py: Python, path: PathBuf, argv: Option<Vec<String>>) -> PyResult<()> {
+        self.scope(py, move |py| {
+            script::run_file(py, &path, argv.unwrap_or_default())
+        })
+    }
+
+    #[pyo3(signature = (name, argv = None))]
+    /// Run a module within the sub-interpreter, like `python -m name *argv` would.
+    ///
+    /// The module runs as `__main__` (using `runpy.run_module`), within its own namespace
+    /// rather than the interpreter's `__main__` namespace. `sys.argv` is set to
+    /// `[module_path, *argv]` while it runs.
+    fn run_module(&self, py: Python, name: String, argv: Option<Vec<String>>) -> PyResult<()> {
+        self.scope(py, move |py| {
+            script::run_module(py, &name, argv.unwrap_or_default())
+        })
+    }
+
+    /// Evaluate a Python expression within the sub-interpreter and return the result.
+    ///
+    /// The result is copied back into the calling interpreter, which means only the following
+    /// types can be returned: `None`, `bool`, `int`, `float`, `str`, `bytes` and any
+    /// `tuple`, `list` or `dict` made up of those. With the `"pickle"` transport, any
+    /// object which can be pickled can be returned instead.
+    fn eval(&self, py: Python, expr: String) -> PyResult<PyObject> {
+        let expr = SourceCode::new(&expr, "<string>", true);
+        let transport = self.0.transport;
+
+        let value = self.scope(py, move |py| {
+            let obj = expr.eval(py)?;
+            transport.dump_remote(obj)
+        })??;
+
+        transport.load(py, value)
+    }
+
+    #[pyo3(signature = (func, *args, **kwargs))]
+    /// Call a function within the sub-interpreter and return the result.
+    ///
+    /// `func` is either a module level function or its qualified name (e.g. `"math.sqrt"`),
+    /// which is imported within the interpreter. Like `eval`, the arguments and result are
+    /// copied between the interpreters and so must be shareable.
+    fn call(
+        &self,
+        py: Python,
+        func: &PyAny,
+        args: &PyTuple,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<PyObject> {
+        let transport = self.0.transport;
+        let value = self.scope(py, call_function(func, args, kwargs, transport)?)??;
+        transport.load(py, value)
+    }
+
+    /// Get a value from the sub-interpreter's `__main__` namespace.
+    ///
+    /// Like `eval`, the value is copied back into the calling interpreter so it must be
+    /// shareable. Raises a `KeyError` if the name is not set.
+    fn get(&self, py: Python, name: String) -> PyResult<PyObject> {
+        let key = name.clone();
+        let transport = self.0.transport;
+        let value = self.scope(py, move |py| {
+            let namespace = main_namespace(py)?;
+            namespace
+                .get_item(key)
+                .map(|obj| transport.dump_remote(obj))
+                .transpose()
+        })?;
+
+        match value {
+            Some(value) => transport.load(py, value?),
+            None => Err(PyKeyError::new_err(name)),
+        }
+    }
+
+    /// Set a value in the sub-interpreter's `__main__` namespace.
+    ///
+    /// The value is copied into the sub-interpreter so, like `eval`, it must be shareable
+    /// unless the interpreter uses the `"pickle"` transport.
+    fn set(&self, py: Python, name: String, value: &PyAny) -> PyResult<()> {
+        let transport = self.0.transport;
+        let value = transport.dump(value)?;
+        self.scope(py, move |py| {
+            let namespace = main_namespace(py)?;
+            namespace.set_item(name, transport.load(py, value)?)
+        })
+    }
+
+    /// Delete a value from the sub-interpreter's `__main__` namespace.
+    ///
+    /// Raises a `KeyError` if the name is not set.
+    fn delete(&self, py: Python, name: String) -> PyResult<()> {
+        let key = name.clone();
+        let removed = self.scope(py, move |py| {
+            let namespace = main_namespace(py)?;
+            if namespace.contains(&key)? {
+                namespace.del_item(&key)?;
+                return Ok(true);
+            }
+            Ok(false)
+        })?;
+
+        if removed {
+            Ok(())
+        } else {
+            Err(PyKeyError::new_err(name))
+        }
+    }
+
+    #[pyo3(signature = (target = None))]
+    /// Redirects everything written to `sys.stdout` and `sys.stderr` within the interpreter.
+    ///
+    /// The `target` can be one of:
+    /// - `None` - Output goes to the process' stdout and stderr, which is the default.
+    /// - `"capture"` - Output is kept in memory until it is read with `read_output`.
+    /// - A callable - Which is called with the stream name (`"stdout"` or `"stderr"`)
+    ///   and the text written. Calls are made in order, but from a background thread.
+    fn redirect_output(&self, py: Python, target: Option<&PyAny>) -> PyResult<()> {
+        let target = OutputTarget::from_py(target)?;
+
+        // Only the interpreter itself holds onto the target, so any callback is
+        // released when the interpreter is shutdown rather than after the main interpreter exits.
+        let buffer = target.buffer();
+        self.scope(py, move |py| target.install(py))?;
+
+        *self.0.output.lock().unwrap() = buffer;
+        Ok(())
+    }
+
+    /// Returns the output captured since the last call, and clears it.
+    ///
+    /// Output is only captured once `redirect_output("capture")` has been called,
+    /// otherwise this is always empty.
+    fn read_output(&self) -> CapturedOutput {
+        match &*self.0.output.lock().unwrap() {
+            Some(buffer) => buffer.take(),
+            None => CapturedOutput::default(),
+        }
+    }
+
+    /// Interrupts the code the interpreter is currently running by raising a
+    /// `KeyboardInterrupt` within it.
+    ///
+    /// Returns `False` if the interpreter isn't running anything. Code blocked within a
+    /// call into C (e.g. a long `time.sleep`) is only interrupted once that call returns.
+    fn interrupt(&self, py: Python) -> bool {
+        let handle = self.0.clone();
+        py.allow_threads(move || handle.info.interrupt.interrupt(Signal::Interrupt))
+    }
+
+    /// Shuts down the interpreter.
+    ///
+    /// Once shutdown, the interpreter cannot be used anymore. Shutting down an
+    /// interpreter which has already been shutdown does nothing.
+    ///
+    /// If the interpreter has a dedicated thread, any work already sent to it is
+    /// completed first.
+    fn shutdown(&self, py: Python) -> PyResult<()> {
+        self.0.finish_shutdown(py, None)?;
+        Ok(())
+    }
+
+    #[getter]
+    /// The ID Python gave the interpreter, which is unique for the lifetime of the process.
+    fn id(&self) -> i64 {
+        self.0.info.id
+    }
+
+    #[getter]
+    /// Where the interpreter is within its lifecycle.
+    fn state(&self) -> InterpreterState {
+        self.0.info.lifecycle.state()
+    }
+
+    #[getter]
+    /// `True` until the interpreter starts shutting down.
+    fn is_alive(&self) -> bool {
+        self.0.info.lifecycle.state().is_alive()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "<SubInterpreter id={} state={:?}>",
+            self.0.info.id,
+            self.0.info.lifecycle.state(),
+        )
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// Shuts down the interpreter when leaving the `with` block.
+    fn __exit__(
+        &self,
+        py: Python,
+        _exc_type: &PyAny,
+        _exc_value: &PyAny,
+        _traceback: &PyAny,
+    ) -> PyResult<bool> {
+        self.shutdown(py)?;
+        Ok(false)
+    }
+}
+
+impl SubInterpreter {
+    fn new(backend: Backend, info: Arc<InterpreterInfo>, transport: Transport) -> Self {
+        let handle = Arc::new(InterpreterHandle {
+            backend,
+            info,
+            output: Mutex::default(),
+            transport,
+        });
+
+        registry::register(Arc::downgrade(&handle) as _);
+        registry::track(&handle.info, Some(&handle));
+        Self(handle)
+    }
+
+    /// Runs the source within the sub-interpreter, see `run_code` for the options.
+    #[allow(clippy::too_many_arguments)]
+    fn run(
+        &self,
+        py: Python,
+        source: Source,
+        globals: Option<&PyDict>,
+        locals: Option<&PyDict>,
+        copy_back: bool,
+        timeout: Option<f64>,
+        capture_output: bool,
+    ) -> PyResult<Option<CapturedOutput>> {
+        let timeout = timeout.map(to_duration).transpose()?;
+        let transport = self.0.transport;
+
+        let shared_globals = globals.map(|dict| SharedNamespace::copy_with(dict, transport));
+        let shared_locals = locals.map(|dict| SharedNamespace::copy_with(dict, transport));
+
+        let buffer = capture_output.then(|| Arc::new(OutputBuffer::default()));
+        let capture = buffer.clone();
+
+        let result = self.scope_with_timeout(py, timeout, move |py| {
+            let globals = shared_globals
+                .map(|ns| ns.into_dict_with(py, transport))
+                .transpose()?;
+            let locals = shared_locals
+                .map(|ns| ns.into_dict_with(py, transport))
+                .transpose()?;
+
+            match capture {
+                Some(buffer) => output::capture(py, buffer, || source.run(py, globals, locals))?,
+                None => source.run(py, globals, locals)?,
+            }
+
+            if !copy_back {
+                return Ok((None, None));
+            }
+
+            Ok((
+                globals.map(|dict| SharedNamespace::copy_with(dict, transport)),
+                locals.map(|dict| SharedNamespace::copy_with(dict, transport)),
+            ))
+        });
+
+        let output = buffer.map(|buffer| buffer.take());
+        let (globals_out, locals_out) = match (result, &output) {
+            (Err(err), Some(output)) => {
+                err.value(py)
+                    .setattr("output", output.clone().into_py(py))?;
+                return Err(err);
+            }
+            (result, _) => result?,
+        };
+
+        if let (Some(dict), Some(namespace)) = (globals, globals_out) {
+            namespace.update_with(dict, transport)?;
+        }
+        if let (Some(dict), Some(namespace)) = (locals, locals_out) {
+            namespace.update_with(dict, transport)?;
+        }
+
+        Ok(output)
+    }
+
+    /// Runs the given function within the sub-interpreter.
+    ///
+    /// If the interpreter has a dedicated thread the function is run there, and the
+    /// caller's GIL is released while waiting for it to complete.
+    ///
+    /// Any error returned by the function is captured within the sub-interpreter and
+    /// raised again within the caller as a `RemoteExecutionError`.
+    ///
+    /// Returns an error if the interpreter has already been shutdown.
+    fn scope<F, T>(&self, py: Python, f: F) -> PyResult<T>
+    where
+        F: FnOnce(Python) -> PyResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.scope_with_timeout(py, None, f)
+    }
+
+    /// Like `scope`, but interrupts the function if it is still running after `timeout`,
+    /// in which case a `TimeoutError` is returned.
+    fn scope_with_timeout<F, T>(&self, py: Python, timeout: Option<Duration>, f: F) -> PyResult<T>
+    where
+        F: FnOnce(Python) -> PyResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.0.info.lifecycle.check_alive()?;
+
+        let interrupt = Arc::downgrade(&self.0.info.interrupt);
+        let f = move |py: Python| {
+            // The timeout only starts once the function does, not while it's waiting to run.
+            let watchdog = match timeout.map(|timeout| Watchdog::start(interrupt, timeout)) {
+                Some(Err(err)) => return (Err(RemoteError::capture(py, err.into())), false),
+                watchdog => watchdog.and_then(Result::ok),
+            };
+
+            let result = f(py).map_err(|err| RemoteError::capture(py, err));
+            let timed_out = watchdog.is_some_and(Watchdog::finish);
+            (result, timed_out)
+        };
+
+        let (result, timed_out) = match &self.0.backend {
+            Backend::Inline(interpreter) => {
+                let lock = lock_inline(interpreter)?;
+
+                // The interpreter may have been shutdown while waiting for the lock.
+                if !lock.is_valid() {
+                    return Err(shutdown_err());
+                }
+
+                // Worker threads track this themselves.
+                let lifecycle = &self.0.info.lifecycle;
+                lock.scope(|py| lifecycle.run(|| f(py)))
+            }
+            Backend::Threaded { jobs, .. } => {
+                let (tx, rx) = mpsc::channel();
+                let job = Job::new(move |py| {
+                    let _ = tx.send(f(py));
+                });
+
+                send_job(jobs, job)?;
+
+                py.allow_threads(move || rx.recv())
+                    .map_err(|_| shutdown_err())?
+            }
+        };
+
+        // Code which caught the interrupt may still have finished, but not in time.
+        if timed_out {
+            let timeout = timeout.unwrap_or_default().as_secs_f64();
+            return Err(PyTimeoutError::new_err(format!(
+                "code did not finish within {timeout} seconds."
+            )));
+        }
+
+        Ok(result?)
+    }
+}
+
+impl Shutdown for InterpreterHandle {
+    fn begin_shutdown(&self) {
+        if let Backend::Threaded { jobs, .. } = &self.backend {
+            if self.info.lifecycle.begin_shutdown() {
+                jobs.lock().unwrap().take();
+            }
+        }
+    }
+
+    fn finish_shutdown(&self, py: Python, deadline: Option<Instant>) -> PyResult<bool> {
+        match &self.backend {
+            Backend::Inline(interpreter) => {
+                let mut lock = lock_inline(interpreter)?;
+                if !self.info.lifecycle.begin_shutdown() {
+                    return Ok(true);
+                }
+
+                lock.shutdown();
+            }
+            Backend::Threaded { jobs, worker } => {
+                if !self.info.lifecycle.begin_shutdown() {
+                    return Ok(true);
+                }
+
+                jobs.lock().unwrap().take();
+                let Some(running) = worker.lock().unwrap().take() else {
+                    // Another thread is already waiting for the worker to exit.
+                    return Ok(true);
+                };
+
+                if let Err(running) = py.allow_threads(move || running.join_until(deadline)) {
+                    *worker.lock().unwrap() = Some(running);
+                    return Ok(false);
+                }
+            }
+        }
+
+        self.info.lifecycle.close();
+        Ok(true)
+    }
+}
+
+impl Drop for InterpreterHandle {
+    fn drop(&mut self) {
+        // Inline interpreters are shutdown when dropped, but a worker thread may still be
+        // finishing its last jobs, so it is left for `shutdown_all` to wait for instead.
+        if let Backend::Threaded { jobs, worker } = &mut self.backend {
+            jobs.get_mut().unwrap().take();
+            if let Some(worker) = worker.get_mut().unwrap().take() {
+                registry::adopt(worker);
+            }
+        }
+    }
+}
+
+/// Locks an inline interpreter for use by the current thread.
+///
+/// This never waits for the lock, the thread holding it would need this thread's GIL
+/// to leave the interpreter again, so waiting would deadlock both of them.
+fn lock_inline(interpreter: &Mutex<Interpreter>) -> PyResult<MutexGuard<'_, Interpreter>> {
+    match interpreter.try_lock() {
+        Ok(lock) => Ok(lock),
+        Err(TryLockError::Poisoned(poisoned)) => Ok(poisoned.into_inner()),
+        Err(TryLockError::WouldBlock) => Err(InterpreterBusyError::new_err(
+            "Interpreter is already running code in another thread.",
+        )),
+    }
+}
+
+/// Queues a job to run on an interpreter's dedicated thread.
+fn send_job(jobs: &Mutex<Option<Sender<Job>>>, job: Job) -> PyResult<()> {
+    jobs.lock()
+        .unwrap()
+        .as_ref()
+        .ok_or_else(shutdown_err)?
+        .send(job)
+        .map_err(|_| shutdown_err())
+}
+
+pub(crate) fn shutdown_err() -> PyErr {
+    InterpreterShutdownError::new_err("Interpreter has shutdown.")
+}
+
+/// Gets the `__main__` module namespace of the currently active interpreter.
+///
+/// This is what `run_code` and `eval` use when no globals are given, so it persists
+/// for the lifetime of the interpreter.
+pub(crate) fn main_namespace(py: Python<'_>) -> PyResult<&PyDict> {
+    Ok(py.import("__main__")?.dict())
+}
+
+/// Gets one of the Python modules embedded within the extension for the currently active
+/// interpreter, importing it from `source` the first time.
+///
+/// Sub-interpreters can't import this extension, so each one gets its own copy of the
+/// module instead, which is kept within `sys.modules` under `name`.
+pub(crate) fn import_embedded<'py>(
+    py: Python<'py>,
+    name: &str,
+    filename: &str,
+    source: &str,
+) -> PyResult<&'py PyModule> {
+    let modules = py.import("sys")?.getattr("modules")?.downcast::<PyDict>()?;
+    if let Some(module) = modules.get_item(name) {
+        return Ok(module.downcast()?);
+    }
+
+    PyModule::from_code(py, source, filename, name)
+}
+
+/// Gets the `str` entries of `sys.path` for the currently active interpreter.
+pub(crate) fn get_sys_path(py: Python<'_>) -> Vec<String> {
+    let path = py.import("sys").and_then(|sys| sys.getattr("path"));
+
+    path.and_then(|path| path.iter())
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok()?.extract::<String>().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Replaces `sys.path` for the currently active interpreter.
+///
+/// Failing to do so is not fatal, so the error is printed rather than returned.
+pub(crate) fn set_sys_path(py: Python<'_>, path: Vec<String>) {
+    let result = py.import("sys").and_then(|sys| sys.setattr("path", path));
+
+    if let Err(e) = result {
+        e.print(py);
+    }
+}
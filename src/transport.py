@@ -0,0 +1,83 @@
+import pickle
+
+# How deep to look within an object for the part of it which cannot be pickled.
+MAX_DEPTH = 64
+
+
+def dumps(obj, protocol):
+    """
+    Pickles the object, raising a `PicklingError` which points at the part of it
+    that couldn't be pickled if that fails.
+    """
+
+    try:
+        return pickle.dumps(obj, protocol)
+    except Exception as e:
+        path, culprit = _find_unpicklable(obj, protocol)
+        raise pickle.PicklingError(
+            f"{path} cannot be pickled, {type(culprit).__qualname__} object {_short_repr(culprit)}: {e}"
+        ) from e
+
+
+def loads(data):
+    return pickle.loads(data)
+
+
+def _find_unpicklable(obj, protocol):
+    path = "value"
+    # Objects which refer back to themselves are only looked within once.
+    visited = {id(obj)}
+
+    for _ in range(MAX_DEPTH):
+        for suffix, child in _children(obj):
+            if id(child) in visited:
+                continue
+            try:
+                pickle.dumps(child, protocol)
+            except Exception:
+                path += suffix
+                obj = child
+                visited.add(id(obj))
+                break
+        else:
+            break
+
+    return path, obj
+
+
+def _children(obj):
+    """
+    Yields the objects pickling `obj` would also pickle, along with where they are.
+    """
+
+    if isinstance(obj, dict):
+        for key, value in obj.items():
+            yield f"[{key!r}]", value
+        for key in obj:
+            yield f" key {_short_repr(key)}", key
+    elif isinstance(obj, (list, tuple)):
+        for i, value in enumerate(obj):
+            yield f"[{i}]", value
+    elif isinstance(obj, (set, frozenset)):
+        for value in obj:
+            yield f" item {_short_repr(value)}", value
+    else:
+        state = getattr(obj, "__dict__", None)
+        if isinstance(state, dict):
+            for name, value in state.items():
+                yield f".{name}", value
+
+        for cls in type(obj).__mro__:
+            slots = getattr(cls, "__slots__", ())
+            for name in (slots,) if isinstance(slots, str) else slots:
+                if hasattr(obj, name):
+                    yield f".{name}", getattr(obj, name)
+
+
+def _short_repr(obj):
+    try:
+        text = repr(obj)
+    except Exception:
+        return "<unrepresentable>"
+
+    return text if len(text) <= 80 else text[:77] + "..."
@@ -0,0 +1,117 @@
+use pyo3::exceptions::PyValueError;
+use pyo3::types::{PyBytes, PyModule};
+use pyo3::{IntoPy, PyAny, PyObject, PyResult, Python};
+
+use crate::shareable::{ShareError, SharedValue};
+use crate::subinterpreter::import_embedded;
+
+/// The name the transport module is imported as within each interpreter.
+const MODULE_NAME: &str = "_subinterpreters_transport";
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+/// How values are copied between interpreters.
+pub(crate) enum Transport {
+    /// Values are copied as `SharedValue`s, which only supports a handful of builtin types.
+    #[default]
+    Shared,
+    /// Values are pickled by the interpreter they are copied from and unpickled by
+    /// the interpreter they are copied into, which supports most objects.
+    ///
+    /// Classes (and functions) are pickled by name, so they must be importable by the
+    /// destination interpreter, which means they can't be defined within `__main__`.
+    /// Channels can't be pickled, so they are still copied as `SharedValue`s.
+    Pickle {
+        /// The pickle protocol to use, or `None` for `pickle.DEFAULT_PROTOCOL`.
+        protocol: Option<i32>,
+    },
+}
+
+impl Transport {
+    /// Gets the transport from either `"shared"` or `"pickle"`.
+    ///
+    /// `pickle_protocol` can only be given along with `"pickle"`.
+    pub(crate) fn from_py(
+        py: Python,
+        transport: &str,
+        pickle_protocol: Option<i32>,
+    ) -> PyResult<Self> {
+        match (transport, pickle_protocol) {
+            ("shared", None) => Ok(Self::Shared),
+            ("shared", Some(_)) => Err(PyValueError::new_err(
+                "`pickle_protocol` can only be given when `transport` is \"pickle\".",
+            )),
+            ("pickle", protocol) => {
+                let highest = py
+                    .import("pickle")?
+                    .getattr("HIGHEST_PROTOCOL")?
+                    .extract::<i32>()?;
+
+                match protocol {
+                    Some(protocol) if protocol > highest => Err(PyValueError::new_err(format!(
+                        "pickle protocol must be at most {highest}, not {protocol}."
+                    ))),
+                    protocol => Ok(Self::Pickle { protocol }),
+                }
+            }
+            (other, _) => Err(PyValueError::new_err(format!(
+                "transport must be either \"shared\" or \"pickle\", not {other:?}."
+            ))),
+        }
+    }
+
+    /// Copies the object out of the currently active interpreter.
+    ///
+    /// When pickling fails, the `PicklingError` says which part of the object could
+    /// not be pickled.
+    pub(crate) fn dump(self, obj: &PyAny) -> PyResult<SharedValue> {
+        let py = obj.py();
+        match self {
+            Self::Shared => Ok(SharedValue::extract_from(py, obj)?),
+            Self::Pickle { protocol } => {
+                // Channels can't be pickled, but they are shareable however they are copied.
+                if let Some(state) = SharedValue::extract_channel(obj) {
+                    return Ok(SharedValue::Channel(state));
+                }
+
+                let data = module(py)?.getattr("dumps")?.call1((obj, protocol))?;
+                Ok(SharedValue::Pickled(
+                    data.downcast::<PyBytes>()?.as_bytes().to_vec(),
+                ))
+            }
+        }
+    }
+
+    /// Like `dump`, but for copying a value out of a sub-interpreter to return to the caller.
+    ///
+    /// A value which isn't shareable is returned as a `ShareError`, so it can be raised again
+    /// within the caller, whereas a pickling error is raised within the sub-interpreter.
+    pub(crate) fn dump_remote(self, obj: &PyAny) -> PyResult<Result<SharedValue, ShareError>> {
+        match self {
+            Self::Shared => Ok(SharedValue::extract_from(obj.py(), obj)),
+            Self::Pickle { .. } => self.dump(obj).map(Ok),
+        }
+    }
+
+    /// Rebuilds a value copied by `dump` within the currently active interpreter.
+    pub(crate) fn load(self, py: Python, value: SharedValue) -> PyResult<PyObject> {
+        match (self, value) {
+            (Self::Pickle { .. }, SharedValue::Pickled(data)) => {
+                let obj = module(py)?
+                    .getattr("loads")?
+                    .call1((PyBytes::new(py, &data),))?;
+                Ok(obj.into())
+            }
+            (_, value) => Ok(value.into_py(py)),
+        }
+    }
+}
+
+/// Gets the transport module for the current interpreter, importing it the first time.
+fn module(py: Python<'_>) -> PyResult<&PyModule> {
+    import_embedded(
+        py,
+        MODULE_NAME,
+        "subinterpreters/transport.py",
+        include_str!("transport.py"),
+    )
+}
@@ -0,0 +1,194 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use pyo3::types::{PyDict, PyTuple};
+use pyo3::{PyAny, PyResult, Python};
+
+use crate::call::{CallArgs, FunctionRef};
+use crate::compile::{Source, SourceCode};
+use crate::exceptions::RemoteError;
+use crate::future::{FutureStart, PendingFuture};
+use crate::registry::InterpreterInfo;
+use crate::shareable::{ShareError, SharedValue};
+use crate::subinterpreter::{get_sys_path, set_sys_path};
+use crate::transport::Transport;
+use crate::{CreateInterpreterError, Interpreter, InterpreterConfig};
+
+/// A unit of work to run within a worker's sub-interpreter.
+pub(crate) struct Job {
+    /// Marks the future waiting on the job as running, if there is one.
+    start: Option<FutureStart>,
+    run: Box<dyn FnOnce(Python) + Send>,
+}
+
+impl Job {
+    pub(crate) fn new(run: impl FnOnce(Python) + Send + 'static) -> Self {
+        Self {
+            start: None,
+            run: Box::new(run),
+        }
+    }
+
+    /// Creates a job which resolves the future with the result of `f`.
+    ///
+    /// The job is skipped if the future is cancelled before a worker picks it up,
+    /// and the future can't be cancelled once it has.
+    pub(crate) fn resolving<F>(pending: PendingFuture, transport: Transport, f: F) -> Self
+    where
+        F: FnOnce(Python) -> PyResult<Result<SharedValue, ShareError>> + Send + 'static,
+    {
+        Self {
+            start: Some(pending.start_handle()),
+            run: Box::new(move |py| {
+                // Errors must be captured while still within the sub-interpreter.
+                let result = f(py).map_err(|err| RemoteError::capture(py, err));
+                pending.resolve(result, transport);
+            }),
+        }
+    }
+}
+
+/// A queue of jobs which can be shared between several workers.
+pub(crate) type JobQueue = Arc<Mutex<Receiver<Job>>>;
+
+/// A dedicated OS thread which owns a sub-interpreter and runs jobs within it.
+///
+/// The sub-interpreter is created, used and shutdown by the worker thread only, which
+/// means it never holds the caller's GIL and can run in parallel with other interpreters.
+///
+/// The thread exits once every sender for its job queue has been dropped and
+/// the remaining jobs have been run.
+pub(crate) struct Worker {
+    handle: JoinHandle<()>,
+    info: Arc<InterpreterInfo>,
+}
+
+impl Worker {
+    /// Spawns a new worker thread and waits for it to create its sub-interpreter.
+    pub(crate) fn spawn(py: Python, config: InterpreterConfig, jobs: JobQueue) -> PyResult<Self> {
+        let (ready_tx, ready_rx) = mpsc::channel();
+
+        let handle = thread::Builder::new()
+            .name("subinterpreter-worker".to_string())
+            .spawn(move || run_worker(config, jobs, ready_tx))?;
+
+        // The worker needs the GIL to create the interpreter, so it must be released here.
+        let info = py
+            .allow_threads(move || ready_rx.recv())
+            .unwrap_or_else(|_| {
+                Err(CreateInterpreterError::Other(
+                    "worker thread exited before creating the interpreter.".to_string(),
+                ))
+            })?;
+
+        Ok(Self { handle, info })
+    }
+
+    /// Details about the worker's sub-interpreter.
+    pub(crate) fn info(&self) -> &Arc<InterpreterInfo> {
+        &self.info
+    }
+
+    /// Waits for the worker thread to exit.
+    pub(crate) fn join(self) {
+        let _ = self.handle.join();
+    }
+
+    /// Waits for the worker thread to exit, giving up once the deadline has passed.
+    ///
+    /// Returns the worker again if it is still running.
+    pub(crate) fn join_until(self, deadline: Option<Instant>) -> Result<(), Self> {
+        let Some(deadline) = deadline else {
+            self.join();
+            return Ok(());
+        };
+
+        while !self.is_finished() {
+            if Instant::now() >= deadline {
+                return Err(self);
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        self.join();
+        Ok(())
+    }
+
+    /// Returns `true` if the worker thread has exited.
+    pub(crate) fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+}
+
+fn run_worker(
+    config: InterpreterConfig,
+    jobs: JobQueue,
+    ready: Sender<Result<Arc<InterpreterInfo>, CreateInterpreterError>>,
+) {
+    let created = Python::with_gil(|py| {
+        let sys_path = get_sys_path(py);
+        Interpreter::create(py, config).map(|interpreter| (interpreter, sys_path))
+    });
+
+    let interpreter = match created {
+        Ok((interpreter, sys_path)) => {
+            interpreter.scope(|py| set_sys_path(py, sys_path));
+            interpreter
+        }
+        Err(e) => {
+            let _ = ready.send(Err(e));
+            return;
+        }
+    };
+
+    let thread = interpreter.thread();
+    let info = Arc::new(InterpreterInfo::new(&interpreter, config, thread));
+    let _ = ready.send(Ok(info.clone()));
+
+    loop {
+        // The queue is only locked while waiting for the next job, not while running it.
+        let job = match jobs.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => break,
+        };
+
+        // Marking the future as running needs the main GIL, so it can't be done
+        // from within the sub-interpreter.
+        if job.start.is_none_or(FutureStart::start) {
+            info.lifecycle.run(|| interpreter.scope(job.run));
+        }
+    }
+
+    // The interpreter is shutdown by this thread once dropped.
+    info.lifecycle.begin_shutdown();
+    drop(interpreter);
+    info.lifecycle.close();
+}
+
+/// Creates a job which runs the Python script within the `__main__` namespace.
+pub(crate) fn run_script(
+    code: String,
+) -> impl FnOnce(Python) -> PyResult<Result<SharedValue, ShareError>> + Send {
+    let source = Source::Text(SourceCode::new(&code, "<string>", true));
+
+    move |py| {
+        source.run(py, None, None)?;
+        Ok(Ok(SharedValue::None))
+    }
+}
+
+/// Creates a job which calls the function with the given arguments, which along with
+/// the result are copied using `transport`.
+pub(crate) fn call_function(
+    func: &PyAny,
+    args: &PyTuple,
+    kwargs: Option<&PyDict>,
+    transport: Transport,
+) -> PyResult<impl FnOnce(Python) -> PyResult<Result<SharedValue, ShareError>> + Send> {
+    let func = FunctionRef::from_py(func)?;
+    let args = CallArgs::extract(args, kwargs, transport)?;
+
+    Ok(move |py: Python| args.call(func.resolve(py)?))
+}